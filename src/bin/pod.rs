@@ -1,25 +1,41 @@
 extern crate libc;
 extern crate serde_json;
 extern crate serde;
-extern crate rouler;
+extern crate clap;
 
 extern crate podesta;
 
 use podesta::manager::Manager as Manager;
 use podesta::libdata::PathList as PathList;
 
+use clap::{App, Arg};
+
 use std::io::{self, Write};
+use std::process;
 
 pub const DATA_DIR: &'static str = "lib/data/";
 pub const NAME_DIR: &'static str = "lib/names/";
 
 fn main() {
+    let args = App::new("pod")
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .value_name("N")
+             .help("Seed for the settlement's random number generator")
+             .takes_value(true))
+        .get_matches();
+    let seed = args.value_of("seed").map(|s| {
+        s.parse::<u64>().expect("Seed must be a non-negative integer!")
+    });
     // Display the welcome message
     println!("{}", podesta::WELCOME_MINI);
     // Initialize the manager
     let pl = PathList::from_dirs(DATA_DIR, NAME_DIR)
         .expect("Invalid lib dirs!");
-    let mut man = Manager::new(&pl, true);
+    let mut man = Manager::new(&pl, true, seed).unwrap_or_else(|e| {
+        println!("Failed to load the data files: {}", e);
+        process::exit(1);
+    });
     let mut input = String::new();
     'game: loop {
         use podesta::interpreter::ParseResult as ParseResult;
@@ -66,7 +82,17 @@ fn main() {
                                           or 'building')!"),
                     }
                 },
-                ParseResult::Repair(bname, qname) => man.repair_building(bname, qname),
+                ParseResult::Repair(bname, qname, points) =>
+                    man.repair_building(bname, qname, points),
+                ParseResult::RepairAll(budget) => man.repair_all(budget),
+                ParseResult::Demolish(bname, qname) => man.demolish_building(bname, qname),
+                ParseResult::Promote(hname) => man.promote(hname),
+                ParseResult::Trigger(ename, qname, bname) =>
+                    man.trigger_event(ename, qname, bname),
+                ParseResult::Custom(qname, bname) => man.custom_event(qname, bname),
+                ParseResult::Spawn(target, name, amount) => man.spawn(target, name, amount),
+                ParseResult::Set(target, s1, s2, s3) => man.set(target, s1, s2, s3),
+                ParseResult::Seed(n) => man.seed(n),
                 ParseResult::ToggleAuto => man.toggle_auto(),
                 ParseResult::ToggleDev => man.toggle_dev(),
                 ParseResult::Commands => println!("{}", podesta::COMMANDS),
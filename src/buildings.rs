@@ -1,19 +1,45 @@
 use std::default;
 use std::fmt;
-use quarters;
 use people;
 use prompts;
 use effects;
 use items;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use rand::{self, Rng};
+use std::collections::BTreeMap;
+use rand::Rng;
 
 // Modifier for how much it costs to repair a building
 // Divide the total cost by the mod.
 const REPAIR_MOD: f64 = 10.0;
 
+// Modifier for how much it costs to restore a ruined building
+// Divide the plan's cost by the mod.
+const RESTORE_MOD: f64 = 2.0;
+
+// Modifier for how much it costs to demolish a building
+// Divide the plan's cost by the mod (and halve it again for ruins).
+const DEMOLISH_MOD: f64 = 5.0;
+
+// Condition lost each step that a building's upkeep goes unpaid.
+const NEGLECT_DECAY: f64 = 2.0;
+
+// Number of steps of unpaid upkeep before a building is abandoned.
+const ABANDON_STEPS: i32 = 10;
+
+// Number of items a building can hold if its plan does not say.
+const DEFAULT_CAPACITY: usize = 2;
+
+// Boost to a building's build speed per unit of its occupants' build
+// contribution (see Hero::contribution).
+pub const HERO_BUILD: f64 = 0.05;
+
+// Gold collected per step per unit of an occupant's gold contribution.
+const HERO_GOLD: f64 = 0.08;
+
+// Boost to a quarter's growth per unit of a hero's growth contribution.
+pub const HERO_GROW: f64 = 0.01;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Building {
     pub name: String,
@@ -22,6 +48,8 @@ pub struct Building {
     pub occupants: Vec<Rc<RefCell<people::Hero>>>,
     pub items: Vec<Rc<RefCell<items::Item>>>,
     pub boosts: effects::EffectFlags,
+    /// Number of steps in a row that the building's upkeep went unpaid.
+    pub unpaid: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,12 +58,21 @@ pub struct Building {
 pub struct BuildingPlan {
     pub name: String,
     pub id: i32,
+    /// The type of the building, which must be one of the quarter types
+    /// (see QuarterType::tags).
     #[serde(rename = "type")]
-    pub btype: quarters::QType,
+    pub btype: String,
     pub preq: Option<Vec<String>>,
     pub cost: f64,
     pub build: f64,
-    pub events: HashMap<String, f64>,
+    /// Gold paid each step to maintain the building once it is in use
+    /// (none if left out).
+    #[serde(default)]
+    pub upkeep: f64,
+    /// The number of items the building can hold (see DEFAULT_CAPACITY).
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    pub events: BTreeMap<String, f64>,
 }
 
 #[derive(Debug)]
@@ -47,26 +84,68 @@ pub enum OccupyError {
     InvalidOccupant,
     /// Item not valid in building.
     InvalidItem,
+    /// Building cannot hold any more items.
+    Full,
+    /// Building is not in ruins.
+    NotRuined,
+}
+
+impl fmt::Display for OccupyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OccupyError::NotInUse => write!(f, "Building is not in use"),
+            OccupyError::InvalidOccupant => write!(f, "Hero cannot occupy the building"),
+            OccupyError::InvalidItem => write!(f, "Item cannot be kept in the building"),
+            OccupyError::Full => write!(f, "Building cannot hold any more items"),
+            OccupyError::NotRuined => write!(f, "Building is not in ruins"),
+        }
+    }
+}
+
+
+fn default_capacity() -> usize {
+    DEFAULT_CAPACITY
 }
 
+#[cfg(test)]
+impl BuildingPlan {
+    /// Return a plan for a residential building of the given name, costing
+    /// 100 gold over 10 steps, with no upkeep and room for one item.
+    /// Tests override the fields they need (e.g. `upkeep: 2.0, ..test(..)`).
+    pub fn test(name: &str) -> BuildingPlan {
+        BuildingPlan {
+            name: name.to_string(),
+            id: 0,
+            btype: String::from("Residential"),
+            preq: None,
+            cost: 100.0,
+            build: 10.0,
+            upkeep: 0.0,
+            capacity: 1,
+            events: BTreeMap::new(),
+        }
+    }
+}
 
 impl fmt::Display for BuildingPlan {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} ({}): pre-reqs {}, costs {}",
+        write!(f, "{} ({}): pre-reqs {}, costs {}, upkeep {}, holds {} items",
                self.name,
                self.btype,
                match self.preq {
                    Some(ref preqs) => (&preqs).join(" & "),
                    None => String::from("n/a"),
                },
-               self.cost)
+               self.cost,
+               self.upkeep,
+               self.capacity)
     }
 }
 
 /// Enum representing condition of a building.
-/// # Improvements
-/// * Add ability to perform repairs for gold.
-/// * Pay gold to remove ruined buildings.
+/// Buildings in use can be repaired for gold, ruined buildings can be
+/// restored (starting their construction over), and any building can be
+/// demolished for a fee.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum BldgCond {
     /// Building is not yet complete (no bonuses); any damage resets to 0.0.
@@ -89,6 +168,13 @@ impl fmt::Display for BldgCond {
     }
 }
 
+impl BldgCond {
+    /// Return true if the building is complete and usable.
+    pub fn is_in_use(&self) -> bool {
+        enum_match!(*self, BldgCond::InUse(_))
+    }
+}
+
 impl default::Default for BldgCond {
     fn default() -> BldgCond { BldgCond::InProgress(0.0) }
 }
@@ -103,114 +189,280 @@ impl Building {
             occupants: vec!(),
             items: vec!(),
             boosts: effects::EffectFlags::default(),
+            unpaid: 0,
         }
     }
 
     /// Execute a timestep for the building, aging it (or progressing in its
     /// construction).
-    /// Add the given bonus to the building (note: bonus can be negative).
-    pub fn step(&mut self) {
+    /// The building's effects are resolved on top of the given boosts of its
+    /// quarter (note: bonuses can be negative).
+    /// Occupants roll for their next activity using the given generator.
+    /// If the building falls into ruin this step, return the occupants
+    /// driven out (see ruin).
+    pub fn step<R: Rng>(&mut self, parent: &effects::Boosts, rng: &mut R)
+        -> Option<Vec<Rc<RefCell<people::Hero>>>>
+    {
+        let boosts = self.boosts.resolve(parent);
+        let mut evicted = None;
         self.cond = match self.cond {
             BldgCond::InProgress(n) => {
                 if n >= 100.0 {
                     BldgCond::InUse(100.0)
                 } else {
-                    BldgCond::InProgress(n + (self.plan.build
-                                              * self.boosts.build.next().unwrap_or(1.0))
-                                         + self.boosts.build_bonus.next().unwrap_or(0.0))
+                    BldgCond::InProgress(n + boosts.build.apply(self.plan.build))
                 }
             },
             BldgCond::InUse(n) => {
                 if n <= 0.0 {
-                    // remove all occupants & items when building becomes ruined
-                    // TODO: should this be handled by the manager?
-                    // TODO: manager can alert that the building is ruined
-                    // TODO: and that the occupants have left and items destroyed
-                    self.occupants.clear();
-                    self.items.clear();
+                    evicted = Some(self.ruin());
                     BldgCond::Ruined
                 } else {
-                    BldgCond::InUse((n - 1.0 + self.boosts.build_bonus.next()
-                                     .unwrap_or(0.0)).min(100.0))
+                    BldgCond::InUse((n - 1.0 + boosts.build.add).min(100.0))
                 }
             },
             BldgCond::Ruined => BldgCond::Ruined,
         };
-        //TODO: kill heroes if building has growth subtraction
-        //TODO: destroy items if building has build subtraction
-        let mut r = rand::thread_rng();
         for hero in self.occupants.iter() {
-            hero.borrow_mut().step(r.gen_range(1, 101));
+            hero.borrow_mut().step(rng.gen_range(1, 101));
         }
         for item in self.items.iter() {
             item.borrow_mut().step();
         }
+        evicted
+    }
+
+    /// Damage the building by the given amount of condition.
+    /// Buildings under construction lose all of their progress, and buildings
+    /// in use are ruined once their condition reaches zero.
+    /// Return the occupants driven out if the building was ruined.
+    pub fn damage(&mut self, amount: f64) -> Vec<Rc<RefCell<people::Hero>>> {
+        match self.cond {
+            BldgCond::InProgress(_) => {
+                self.cond = BldgCond::InProgress(0.0);
+                vec![]
+            },
+            BldgCond::InUse(n) if n - amount > 0.0 => {
+                self.cond = BldgCond::InUse(n - amount);
+                vec![]
+            },
+            BldgCond::InUse(_) => self.ruin(),
+            BldgCond::Ruined => vec![],
+        }
+    }
+
+    /// Force the building into the given condition, ruining it if need be.
+    /// Return the occupants driven out (see ruin).
+    pub fn set_cond(&mut self, cond: BldgCond) -> Vec<Rc<RefCell<people::Hero>>> {
+        match cond {
+            BldgCond::Ruined if self.cond != BldgCond::Ruined => self.ruin(),
+            _ => {
+                self.cond = cond;
+                vec![]
+            },
+        }
+    }
+
+    /// Ruin the building, destroying its items.
+    /// Occupants who are away are immune and keep their place; the rest are
+    /// driven out and returned.
+    fn ruin(&mut self) -> Vec<Rc<RefCell<people::Hero>>> {
+        self.cond = BldgCond::Ruined;
+        self.items.clear();
+        let (away, evicted) = self.occupants.drain(..)
+            .partition(|h| h.borrow().is_away());
+        self.occupants = away;
+        evicted
     }
 
-    /// Return the cost to repair the building back to 100% (BldgCond::InUse(100.0)).
+    /// Strike each occupant with a town effect that kills with the given
+    /// chance (see Hero::strike).
+    /// Return the occupants who were killed.
+    pub fn kill_occupants<R: Rng>(&mut self, chance: f64, cause: &str, rng: &mut R)
+        -> Vec<Rc<RefCell<people::Hero>>>
+    {
+        self.occupants.iter()
+            .filter(|h| h.borrow_mut().strike(chance, cause, rng))
+            .cloned().collect()
+    }
+
+    /// Return the cost to repair the given points of the building's condition,
+    /// or to repair it back to 100% (BldgCond::InUse(100.0)) if None.
+    /// Repairs never take the building past 100%.
+    /// For a ruined building, return the cost to restore it (see restore).
     /// Return an Error if the building is InProgress.
-    pub fn get_rep_cost(&self) -> Result<f64, OccupyError> {
+    pub fn get_rep_cost(&self, points: Option<f64>) -> Result<f64, OccupyError> {
+        match self.cond {
+            BldgCond::InUse(c) => {
+                let points = points.unwrap_or(100.0).min(100.0 - c).max(0.0);
+                // take a percentage of the base depending on how much is repaired
+                Ok(self.get_rep_rate() * points)
+            },
+            BldgCond::Ruined => Ok(self.plan.cost / RESTORE_MOD),
+            BldgCond::InProgress(_) => Err(OccupyError::NotInUse)
+        }
+    }
+
+    /// Return the cost to repair a single point of the building's condition.
+    pub fn get_rep_rate(&self) -> f64 {
+        self.plan.cost / REPAIR_MOD / 100.0
+    }
+
+    /// Repair the given points of the building's condition immediately,
+    /// up to 100%, reclaiming it if it was abandoned. Return the points
+    /// actually repaired, or an Error if the building is not in use.
+    pub fn repair(&mut self, points: f64) -> Result<f64, OccupyError> {
         match self.cond {
             BldgCond::InUse(c) => {
-                let base = self.plan.cost / REPAIR_MOD;
-                // take a percentage of the base depending on how destroyed the building is
-                Ok(base * (100.0 - c) / 100.0)
+                let points = points.min(100.0 - c).max(0.0);
+                self.cond = BldgCond::InUse(c + points);
+                self.unpaid = 0;
+                Ok(points)
             },
-            //TODO: allow ruined buildings to be repaired
-            _ => Err(OccupyError::NotInUse)
+            _ => Err(OccupyError::NotInUse),
+        }
+    }
+
+    /// Restore a ruined building, starting its construction over.
+    /// Return an Error if the building is not ruined.
+    pub fn restore(&mut self) -> Result<(), OccupyError> {
+        match self.cond {
+            BldgCond::Ruined => {
+                self.cond = BldgCond::InProgress(0.0);
+                self.unpaid = 0;
+                Ok(())
+            },
+            _ => Err(OccupyError::NotRuined),
+        }
+    }
+
+    /// Return the gold owed this step to maintain the building.
+    /// Only buildings in use need upkeep, and none is owed for a building
+    /// once it has been abandoned.
+    pub fn get_upkeep(&self) -> f64 {
+        if self.cond.is_in_use() && !self.is_abandoned() { self.plan.upkeep } else { 0.0 }
+    }
+
+    /// Record that the building's upkeep was paid this step.
+    pub fn pay_upkeep(&mut self) {
+        self.unpaid = 0;
+    }
+
+    /// Neglect the building for a step when its upkeep cannot be paid,
+    /// reducing its condition. Once it has gone unpaid for long enough, the
+    /// building is abandoned and its occupants (other than any who are away)
+    /// leave. Return the occupants who left.
+    pub fn neglect(&mut self) -> Vec<Rc<RefCell<people::Hero>>> {
+        self.unpaid += 1;
+        if let BldgCond::InUse(n) = self.cond {
+            self.cond = BldgCond::InUse((n - NEGLECT_DECAY).max(0.0));
+        }
+        if self.is_abandoned() {
+            let (away, left) = self.occupants.drain(..)
+                .partition(|h| h.borrow().is_away());
+            self.occupants = away;
+            left
+        } else {
+            vec![]
         }
     }
 
+    /// Return true if the building has been abandoned for lack of upkeep.
+    /// Heroes will not make an abandoned building their headquarters until
+    /// it is repaired (or restored).
+    pub fn is_abandoned(&self) -> bool {
+        self.unpaid >= ABANDON_STEPS
+    }
+
+    /// Return the fee to demolish the building. Ruins are cheaper to clear.
+    pub fn get_demolish_cost(&self) -> f64 {
+        match self.cond {
+            BldgCond::Ruined => self.plan.cost / DEMOLISH_MOD / 2.0,
+            _ => self.plan.cost / DEMOLISH_MOD,
+        }
+    }
+
+    /// Return true if heroes of the given class may make the building
+    /// their headquarters.
+    pub fn accepts_class(&self, class: &people::Class) -> bool {
+        class.bldgs.iter().any(|b| *b == self.plan.name || b == "ALL")
+    }
+
     /// Add occupant to building.
-    /// Return an Error if the building cannot accept the occupant.
+    /// Return an Error if the building cannot accept the occupant:
+    /// only buildings listed by the hero's class may be occupied.
     pub fn add_occupant(&mut self, hero: Rc<RefCell<people::Hero>>) -> Result<(), OccupyError> {
         match self.cond {
+            BldgCond::InUse(_) if self.is_abandoned() => Err(OccupyError::NotInUse),
             BldgCond::InUse(_) => {
-                self.occupants.push(hero);
-                Ok(())
-                //TODO: only allow buildings specified by hero's class
-                //TODO: would require changes to manager::rand_building()
-                /*
-                if hero.borrow().class.bldgs.contains(&self.name) {
+                if self.accepts_class(&hero.borrow().class) {
                     self.occupants.push(hero);
                     Ok(())
                 } else {
                     Err(OccupyError::InvalidOccupant)
                 }
-                */
             },
             _ => Err(OccupyError::NotInUse),
         }
     }
 
-    /// Add item to building.
-    /// Return an Error if the building cannot accept the item.
-    pub fn add_item(&mut self, item: Rc<RefCell<items::Item>>) -> Result<(), OccupyError> {
-        println!("{:?}", item);
+    /// Check that the building can hold the given item: it must be in use,
+    /// its plan must be able to keep items of the item's kind, and it must
+    /// have room left (see BuildingPlan::capacity).
+    pub fn can_hold(&self, item: &items::Item) -> Result<(), OccupyError> {
         match self.cond {
-            BldgCond::InUse(_) => {
-                //TODO: add element to building plan specifying
-                //TODO: kinds of items building can hold
-                self.items.push(item);
-                Ok(())
-            },
+            BldgCond::InUse(_) if !item.kind.allows(&self.plan) => Err(OccupyError::InvalidItem),
+            BldgCond::InUse(_) if self.items.len() >= self.plan.capacity => Err(OccupyError::Full),
+            BldgCond::InUse(_) => Ok(()),
             _ => Err(OccupyError::NotInUse),
         }
     }
 
-    /// Collect gold.
-    /// For each occupant of the building, collect an extra 0.04 gold.
+    /// Add item to building.
+    /// Return an Error if the building cannot accept the item (see can_hold).
+    pub fn add_item(&mut self, item: Rc<RefCell<items::Item>>) -> Result<(), OccupyError> {
+        self.can_hold(&item.borrow())?;
+        self.items.push(item);
+        Ok(())
+    }
+
+    /// Push the contributions of the building's occupants into its boosts
+    /// for the coming step: build speed from working, governing and trading
+    /// heroes, and gold from trading heroes.
+    /// Return the occupants' total growth boost for the building's quarter.
+    pub fn add_contributions(&mut self) -> f64 {
+        let (grow, build, gold) = self.occupants.iter()
+            .fold((0.0, 0.0, 0.0), |(gw, bu, gd), h| {
+                let h = h.borrow();
+                (gw + h.contribution("Growth"),
+                 bu + h.contribution("Build"),
+                 gd + h.contribution("Gold"))
+            });
+        self.boosts.add(effects::Modifier::new("Heroes at work", "occupants",
+                                               effects::Target::Build, effects::Combine::Mul,
+                                               1.0 + HERO_BUILD * build, 1));
+        self.boosts.add(effects::Modifier::new("Heroes at work", "occupants",
+                                               effects::Target::Gold, effects::Combine::Add,
+                                               HERO_GOLD * gold, 1));
+        HERO_GROW * grow
+    }
+
+    /// Collect gold, resolving the building's effects on top of the given
+    /// boosts of its quarter.
+    /// Collect any gold bonus, including the contributions of trading
+    /// occupants (see add_contributions).
     /// For each item in the building, collect an extra 0-0.0x gold, where
     /// x is the item's power.
-    pub fn collect_gold(&mut self) -> f64 {
-        let boost : f64 = self.boosts.gold.next().unwrap_or(1.0);
-        (self.occupants.len() as f64 * 0.04f64
-         + self.items.iter().fold(0.0, |acc, x| acc + x.borrow().collect_gold() * boost))
+    /// Return the gold from the building itself and from its items.
+    pub fn collect_gold(&self, parent: &effects::Boosts) -> (f64, f64) {
+        let boost = self.boosts.resolve(parent).gold;
+        let items = self.items.iter().fold(0.0, |acc, x| acc + x.borrow().collect_gold());
+        (boost.add, boost.mul * items)
     }
 
     /// Get a new map of event chances for each event possible at the building
     /// based on the building's condition.
-    pub fn get_events(&self) -> HashMap<String, f64> {
+    pub fn get_events(&self) -> BTreeMap<String, f64> {
         let mut events = self.plan.events.clone();
         events.iter_mut().map(|(nm, ch)| {
             (nm.clone(), *ch *
@@ -220,7 +472,7 @@ impl Building {
                  // otherwise, event has no chance of occurring
                  _ => 0.0,
              })
-        }).collect::<HashMap<_,_>>()
+        }).collect::<BTreeMap<_,_>>()
     }
 
     /// Find the hero with the given name in the building.
@@ -238,11 +490,192 @@ impl prompts::Described for Building {
 
 impl fmt::Display for Building {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} - {} - {} - hosts {} people, {} items",
+        write!(f, "{} - {} - {}{} - hosts {} people, {} items",
                self.name,
                self.plan.btype,
                self.cond,
+               if self.is_abandoned() { " (abandoned)" } else { "" },
                self.occupants.len(),
                self.items.len())
    }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng;
+    use serde_json;
+
+    fn plan(name: &str) -> Rc<BuildingPlan> {
+        Rc::new(BuildingPlan { upkeep: 2.0, ..BuildingPlan::test(name) })
+    }
+
+    fn in_use(name: &str) -> Building {
+        let mut b = Building::new(plan(name));
+        b.cond = BldgCond::InUse(100.0);
+        b
+    }
+
+    /// Return a hero whose class may live in the listed buildings.
+    fn hero(bldgs: &[&str]) -> Rc<RefCell<people::Hero>> {
+        people::Hero::test("Ann", people::Class {
+            bldgs: bldgs.iter().map(|b| b.to_string()).collect(),
+            ..people::Class::default()
+        })
+    }
+
+    #[test]
+    fn plans_without_upkeep_or_capacity_still_load() {
+        let plan: BuildingPlan = serde_json::from_str(r#"{
+            "name": "Hut", "id": 0, "type": "Residential", "preq": null,
+            "cost": 10, "build": 10, "events": {}
+        }"#).unwrap();
+        assert_eq!(plan.upkeep, 0.0);
+        assert_eq!(plan.capacity, DEFAULT_CAPACITY);
+    }
+
+    #[test]
+    fn heroes_live_in_their_class_buildings() {
+        let mut b = in_use("Temple");
+        assert!(b.add_occupant(hero(&["Shrine", "Temple"])).is_ok());
+        assert!(b.add_occupant(hero(&["ALL"])).is_ok());
+        match b.add_occupant(hero(&["Forge"])) {
+            Err(OccupyError::InvalidOccupant) => (),
+            r => panic!("expected InvalidOccupant, got {:?}", r),
+        }
+        assert_eq!(b.occupants.len(), 2);
+    }
+
+    #[test]
+    fn unfinished_buildings_take_no_occupants() {
+        let mut b = Building::new(plan("Temple"));
+        match b.add_occupant(hero(&["Temple"])) {
+            Err(OccupyError::NotInUse) => (),
+            r => panic!("expected NotInUse, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn occupants_add_their_contributions() {
+        let mut b = in_use("Market");
+        let worker = hero(&["ALL"]);
+        let trader = hero(&["ALL"]);
+        {
+            let mut t = trader.borrow_mut();
+            let mut acts = t.class.act_boosts.clone();
+            acts.insert(String::from("Trading"), 1.0);
+            t.class = Rc::new(people::Class { act_boosts: acts, ..people::Class::default() });
+            t.activity = people::Activity::Trading(3);
+        }
+        b.add_occupant(worker).unwrap();
+        b.add_occupant(trader).unwrap();
+        let grow = b.add_contributions();
+        assert!((grow - 2.0 * HERO_GROW).abs() < 1e-12);
+        let boosts = b.boosts.resolve(&effects::Boosts::default());
+        assert!((boosts.build.mul - (1.0 + 2.0 * HERO_BUILD)).abs() < 1e-12);
+        // only the trader brings in gold
+        assert!((boosts.gold.add - HERO_GOLD).abs() < 1e-12);
+    }
+
+    #[test]
+    fn decaying_to_ruin_drives_out_occupants() {
+        let mut rng = rng::SimRng::new(1);
+        let mut b = in_use("Temple");
+        b.add_occupant(hero(&["Temple"])).unwrap();
+        assert!(b.step(&effects::Boosts::default(), &mut rng).is_none());
+        b.cond = BldgCond::InUse(0.0);
+        let evicted = b.step(&effects::Boosts::default(), &mut rng).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert!(b.occupants.is_empty());
+        assert_eq!(b.cond, BldgCond::Ruined);
+        // a ruin evicts nobody further
+        assert!(b.step(&effects::Boosts::default(), &mut rng).is_none());
+    }
+
+    #[test]
+    fn ruins_are_restored_from_scratch() {
+        let mut b = in_use("Temple");
+        match b.restore() {
+            Err(OccupyError::NotRuined) => (),
+            r => panic!("expected NotRuined, got {:?}", r),
+        }
+        b.damage(100.0);
+        assert_eq!(b.get_rep_cost(None).unwrap(), 100.0 / RESTORE_MOD);
+        assert!(b.restore().is_ok());
+        assert_eq!(b.cond, BldgCond::InProgress(0.0));
+    }
+
+    #[test]
+    fn ruins_are_cheaper_to_demolish() {
+        let mut b = in_use("Temple");
+        assert_eq!(b.get_demolish_cost(), 100.0 / DEMOLISH_MOD);
+        b.damage(100.0);
+        assert_eq!(b.get_demolish_cost(), 100.0 / DEMOLISH_MOD / 2.0);
+    }
+
+    #[test]
+    fn repairs_stop_at_full_condition() {
+        let mut b = in_use("Temple");
+        b.cond = BldgCond::InUse(50.0);
+        let rate = b.get_rep_rate();
+        assert_eq!(b.get_rep_cost(Some(20.0)).unwrap(), 20.0 * rate);
+        assert_eq!(b.get_rep_cost(None).unwrap(), 50.0 * rate);
+        assert_eq!(b.repair(20.0).unwrap(), 20.0);
+        assert_eq!(b.cond, BldgCond::InUse(70.0));
+        // only the missing points are repaired (and paid for)
+        assert_eq!(b.get_rep_cost(Some(50.0)).unwrap(), 30.0 * rate);
+        assert_eq!(b.repair(50.0).unwrap(), 30.0);
+        assert_eq!(b.cond, BldgCond::InUse(100.0));
+    }
+
+    #[test]
+    fn unfinished_buildings_cannot_be_repaired() {
+        let mut b = Building::new(plan("Temple"));
+        assert!(b.get_rep_cost(None).is_err());
+        assert!(b.repair(10.0).is_err());
+    }
+
+    #[test]
+    fn paying_upkeep_clears_neglect() {
+        let mut b = in_use("Temple");
+        b.neglect();
+        b.neglect();
+        assert_eq!(b.unpaid, 2);
+        assert_eq!(b.cond, BldgCond::InUse(100.0 - 2.0 * NEGLECT_DECAY));
+        b.pay_upkeep();
+        assert_eq!(b.unpaid, 0);
+        assert!(!b.is_abandoned());
+        assert_eq!(b.get_upkeep(), 2.0);
+    }
+
+    #[test]
+    fn neglected_buildings_are_abandoned() {
+        let mut b = in_use("Temple");
+        b.add_occupant(hero(&["Temple"])).unwrap();
+        for _ in 1..ABANDON_STEPS {
+            assert!(b.neglect().is_empty());
+        }
+        assert_eq!(b.neglect().len(), 1);
+        assert!(b.is_abandoned());
+        assert!(b.occupants.is_empty());
+        // no more upkeep is owed, and heroes stay away
+        assert_eq!(b.get_upkeep(), 0.0);
+        match b.add_occupant(hero(&["Temple"])) {
+            Err(OccupyError::NotInUse) => (),
+            r => panic!("expected NotInUse, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn repairs_reclaim_abandoned_buildings() {
+        let mut b = in_use("Temple");
+        for _ in 0..ABANDON_STEPS {
+            b.neglect();
+        }
+        assert!(b.is_abandoned());
+        b.repair(100.0).unwrap();
+        assert!(!b.is_abandoned());
+        assert_eq!(b.get_upkeep(), 2.0);
+        assert!(b.add_occupant(hero(&["Temple"])).is_ok());
+    }
+}
@@ -0,0 +1,334 @@
+//! Dice expressions used by effects, e.g. `"5d6 * 2 - 20"`.
+//!
+//! Expressions are built from integers, `NdM` rolls, `+`, `-`, `*`, `/` and
+//! parentheses. Rolls bind tightest, then multiplication and division, then
+//! addition and subtraction; all operators are left-associative.
+//! Rolls draw from the given generator so results can be reproduced.
+
+use rand::Rng;
+use std::fmt;
+use std::error;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Possible errors when parsing or rolling a dice expression.
+#[derive(Debug, PartialEq)]
+pub enum DiceError {
+    /// Unexpected character (or end of input) at the given position.
+    Syntax(usize),
+    /// A roll was made with fewer than one side.
+    NoSides,
+    /// A roll was made with zero dice.
+    NoDice,
+    /// Division by zero.
+    DivideByZero,
+    /// A result too large to hold.
+    Overflow,
+}
+
+impl fmt::Display for DiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiceError::Syntax(pos) => write!(f, "Invalid dice syntax at position {}", pos),
+            DiceError::NoSides => write!(f, "Dice must have at least one side"),
+            DiceError::NoDice => write!(f, "Number of dice must not be zero"),
+            DiceError::DivideByZero => write!(f, "Division by zero"),
+            DiceError::Overflow => write!(f, "Result is too large"),
+        }
+    }
+}
+
+impl error::Error for DiceError {
+    fn description(&self) -> &str {
+        match *self {
+            DiceError::Syntax(_) => "invalid syntax",
+            DiceError::NoSides => "no sides",
+            DiceError::NoDice => "no dice",
+            DiceError::DivideByZero => "division by zero",
+            DiceError::Overflow => "overflow",
+        }
+    }
+}
+
+/// A binary operator in a dice expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Roll,
+}
+
+/// The least, greatest and average results of a dice expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: i64,
+    pub max: i64,
+    /// The average result (approximate when dividing by or rolling a
+    /// number of sides that is not fixed).
+    pub mean: f64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "min {}, max {}, mean {:.1}", self.min, self.max, self.mean)
+    }
+}
+
+/// A parsed dice expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Bin(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse the given string into an Expr.
+    pub fn parse(s: &str) -> Result<Expr, DiceError> {
+        let mut p = Parser { chars: s.char_indices().peekable(), len: s.len() };
+        let e = p.sum()?;
+        p.skip_ws();
+        match p.chars.peek() {
+            Some(&(i, _)) => Err(DiceError::Syntax(i)),
+            None => Ok(e),
+        }
+    }
+
+    /// Evaluate the expression, rolling any dice with the given generator.
+    pub fn eval<R: Rng>(&self, rng: &mut R) -> Result<i64, DiceError> {
+        match *self {
+            Expr::Num(n) => Ok(n),
+            Expr::Bin(op, ref l, ref r) => {
+                let x = l.eval(rng)?;
+                let y = r.eval(rng)?;
+                match op {
+                    Op::Add => checked(x.checked_add(y)),
+                    Op::Sub => checked(x.checked_sub(y)),
+                    Op::Mul => checked(x.checked_mul(y)),
+                    Op::Div => if y == 0 {
+                        Err(DiceError::DivideByZero)
+                    } else {
+                        checked(x.checked_div(y))
+                    },
+                    Op::Roll => {
+                        if y < 1 {
+                            Err(DiceError::NoSides)
+                        } else if x == 0 {
+                            Err(DiceError::NoDice)
+                        } else {
+                            // check the greatest total before rolling
+                            checked(x.checked_abs().and_then(|n| n.checked_mul(y)))?;
+                            let total = (0..x.abs())
+                                .fold(0, |acc, _| acc + rng.gen_range(1, y + 1));
+                            Ok(total * x.signum())
+                        }
+                    },
+                }
+            },
+        }
+    }
+
+    /// Return the least, greatest and average results of the expression.
+    /// Return an error if any roll of the expression could fail, or if any
+    /// result could overflow.
+    pub fn stats(&self) -> Result<Stats, DiceError> {
+        match *self {
+            Expr::Num(n) => Ok(Stats { min: n, max: n, mean: n as f64 }),
+            Expr::Bin(op, ref l, ref r) => {
+                let x = l.stats()?;
+                let y = r.stats()?;
+                let corners = |f: &dyn Fn(i64, i64) -> Option<i64>| {
+                    let v = [f(x.min, y.min), f(x.min, y.max), f(x.max, y.min), f(x.max, y.max)]
+                        .iter().map(|&c| checked(c)).collect::<Result<Vec<_>, _>>()?;
+                    Ok((v.iter().cloned().min().unwrap_or(0), v.iter().cloned().max().unwrap_or(0)))
+                };
+                let (min, max, mean) = match op {
+                    Op::Add => (checked(x.min.checked_add(y.min))?,
+                                checked(x.max.checked_add(y.max))?, x.mean + y.mean),
+                    Op::Sub => (checked(x.min.checked_sub(y.max))?,
+                                checked(x.max.checked_sub(y.min))?, x.mean - y.mean),
+                    Op::Mul => {
+                        let (min, max) = corners(&|a, b| a.checked_mul(b))?;
+                        (min, max, x.mean * y.mean)
+                    },
+                    Op::Div => {
+                        if y.min <= 0 && y.max >= 0 {
+                            return Err(DiceError::DivideByZero);
+                        }
+                        let (min, max) = corners(&|a, b| a.checked_div(b))?;
+                        (min, max, x.mean / y.mean)
+                    },
+                    Op::Roll => {
+                        if y.min < 1 {
+                            return Err(DiceError::NoSides);
+                        }
+                        if x.min <= 0 && x.max >= 0 {
+                            return Err(DiceError::NoDice);
+                        }
+                        // each die rolls from 1 to its sides; a negative
+                        // number of dice negates the total
+                        let min = if x.min > 0 { x.min } else { checked(x.min.checked_mul(y.max))? };
+                        let max = if x.max > 0 { checked(x.max.checked_mul(y.max))? } else { x.max };
+                        (min, max, x.mean * (y.mean + 1.0) / 2.0)
+                    },
+                };
+                Ok(Stats { min, max, mean })
+            },
+        }
+    }
+}
+
+/// Return the given result of checked arithmetic, or an error if it
+/// overflowed.
+fn checked(x: Option<i64>) -> Result<i64, DiceError> {
+    x.ok_or(DiceError::Overflow)
+}
+
+/// Return the stats of the given dice expression (see Expr::stats).
+pub fn stats(s: &str) -> Result<Stats, DiceError> {
+    Expr::parse(s).and_then(|e| e.stats())
+}
+
+/// Roll the given dice expression with the given generator.
+/// Return an error if the expression is invalid or cannot be rolled.
+pub fn roll<R: Rng>(s: &str, rng: &mut R) -> Result<i64, DiceError> {
+    Expr::parse(s).and_then(|e| e.eval(rng))
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&mut self) {
+        while self.chars.peek().map(|&(_, c)| c.is_whitespace()).unwrap_or(false) {
+            self.chars.next();
+        }
+    }
+
+    /// Consume the next operator if it is one of the given ops.
+    fn op(&mut self, ops: &[Op]) -> Option<Op> {
+        self.skip_ws();
+        let op = match self.chars.peek() {
+            Some(&(_, '+')) => Op::Add,
+            Some(&(_, '-')) => Op::Sub,
+            Some(&(_, '*')) => Op::Mul,
+            Some(&(_, '/')) => Op::Div,
+            Some(&(_, 'd')) | Some(&(_, 'D')) => Op::Roll,
+            _ => return None,
+        };
+        if ops.contains(&op) {
+            self.chars.next();
+            Some(op)
+        } else {
+            None
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, DiceError> {
+        let mut e = self.product()?;
+        while let Some(op) = self.op(&[Op::Add, Op::Sub]) {
+            e = Expr::Bin(op, Box::new(e), Box::new(self.product()?));
+        }
+        Ok(e)
+    }
+
+    fn product(&mut self) -> Result<Expr, DiceError> {
+        let mut e = self.dice()?;
+        while let Some(op) = self.op(&[Op::Mul, Op::Div]) {
+            e = Expr::Bin(op, Box::new(e), Box::new(self.dice()?));
+        }
+        Ok(e)
+    }
+
+    fn dice(&mut self) -> Result<Expr, DiceError> {
+        let mut e = self.atom()?;
+        while let Some(op) = self.op(&[Op::Roll]) {
+            e = Expr::Bin(op, Box::new(e), Box::new(self.atom()?));
+        }
+        Ok(e)
+    }
+
+    fn atom(&mut self) -> Result<Expr, DiceError> {
+        self.skip_ws();
+        match self.chars.peek().cloned() {
+            Some((_, '(')) => {
+                self.chars.next();
+                let e = self.sum()?;
+                self.skip_ws();
+                match self.chars.next() {
+                    Some((_, ')')) => Ok(e),
+                    Some((i, _)) => Err(DiceError::Syntax(i)),
+                    None => Err(DiceError::Syntax(self.len)),
+                }
+            },
+            Some((start, c)) if c == '-' || c.is_ascii_digit() => {
+                let mut s = String::new();
+                if c == '-' {
+                    s.push(c);
+                    self.chars.next();
+                }
+                while let Some(&(_, d)) = self.chars.peek() {
+                    if !d.is_ascii_digit() { break; }
+                    s.push(d);
+                    self.chars.next();
+                }
+                s.parse::<i64>().map(Expr::Num).map_err(|_| DiceError::Syntax(start))
+            },
+            Some((i, _)) => Err(DiceError::Syntax(i)),
+            None => Err(DiceError::Syntax(self.len)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng;
+
+    #[test]
+    fn rolls_stay_within_their_stats() {
+        let mut rng = rng::SimRng::new(1);
+        let st = stats("2d6 + 3").unwrap();
+        for _ in 0..50 {
+            let x = roll("2d6 + 3", &mut rng).unwrap();
+            assert!(x >= st.min && x <= st.max);
+        }
+    }
+
+    #[test]
+    fn bad_rolls_are_errors_not_panics() {
+        let mut rng = rng::SimRng::new(1);
+        assert_eq!(roll("1d0", &mut rng), Err(DiceError::NoSides));
+        assert_eq!(roll("0d6", &mut rng), Err(DiceError::NoDice));
+        assert_eq!(roll("6/0", &mut rng), Err(DiceError::DivideByZero));
+        assert_eq!(roll("2d", &mut rng), Err(DiceError::Syntax(2)));
+    }
+
+    #[test]
+    fn stats_of_sums_and_negative_rolls() {
+        assert_eq!(stats("2d6+3"), Ok(Stats { min: 5, max: 15, mean: 10.0 }));
+        assert_eq!(stats("-1d4"), Ok(Stats { min: -4, max: -1, mean: -2.5 }));
+    }
+
+    #[test]
+    fn stats_of_division() {
+        assert_eq!(stats("10/2"), Ok(Stats { min: 5, max: 5, mean: 5.0 }));
+        assert_eq!(stats("10/-2"), Ok(Stats { min: -5, max: -5, mean: -5.0 }));
+        let st = stats("1d6/-1").unwrap();
+        assert_eq!((st.min, st.max), (-6, -1));
+        assert_eq!(stats("6/(1d2-1)"), Err(DiceError::DivideByZero));
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        let mut rng = rng::SimRng::new(1);
+        assert_eq!(stats("9223372036854775807 * 2"), Err(DiceError::Overflow));
+        assert_eq!(stats("9223372036854775807 + 1d2"), Err(DiceError::Overflow));
+        assert_eq!(stats("2d9223372036854775807"), Err(DiceError::Overflow));
+        assert_eq!(roll("9223372036854775807 * 2", &mut rng), Err(DiceError::Overflow));
+        assert_eq!(roll("2d9223372036854775807", &mut rng), Err(DiceError::Overflow));
+    }
+}
@@ -1,29 +1,51 @@
-use rand::{self, Rng};
-use rouler::Roller;
-use quarters::QType;
-use items;
+use rand::Rng;
+use dice::{self, DiceError};
+use serde_json;
 use std::str;
 use std::default;
-use std::ops::{Add, Mul, AddAssign, MulAssign};
+use std::fmt;
 
 /// An enum to determine what part of the settlement the effect should change.
 /// There are three general choices: Building, Quarter, and Sett.
 /// Filters can also restrict what kind of area can be chosen if Building or
-/// Quarter is selected (e.g. a Building or Quarter of a particular QType)
-/// TODO: should other filters than QType(s) be possible?
-/// TODO: may need to change .json files to specify QType filters
+/// Quarter is selected: by building name or type (e.g. "Forge", or a quarter
+/// type named as in lib/data/quarters.json), by the majority race of a
+/// quarter, or by the condition of a building. An area can also be chosen
+/// relative to the building whose event is firing (its source).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Area {
-    Building(Vec<QType>),
-    Quarter(Vec<QType>),
+    /// A random standing building of the listed names or types.
+    Building(Vec<String>),
+    /// Every building in a random quarter of the listed types, or holding
+    /// a building of the listed names (any quarter if none are listed).
+    Quarter(Vec<String>),
+    /// Every building in a random quarter whose majority is one of the
+    /// listed races.
+    Race(Vec<String>),
+    /// A random building in use whose condition is at most $1 (in %).
+    Condition(f64),
+    /// The building that triggered the event.
+    Source,
+    /// Every building in the quarter of the building that triggered the event.
+    SourceQuarter,
+    /// Every building in the quarters adjacent to (founded just before or
+    /// just after) the quarter of the building that triggered the event.
+    Adjacent,
     Sett,
 }
 
 impl Area {
+    /// Return true if the area strikes single buildings rather than whole
+    /// quarters (or the whole sett).
+    pub fn is_building(&self) -> bool {
+        enum_match!(*self, Area::Building(_) | Area::Condition(_) | Area::Source)
+    }
+
     /// Upgrade to a wider-scale Area.
     fn upgrade(self) -> Area {
         match self {
             Area::Building(v) => Area::Quarter(v),
+            Area::Source => Area::SourceQuarter,
             _ => Area::Sett,
         }
     }
@@ -36,22 +58,24 @@ impl Area {
 /// TODO: should there be a trait for stepping?
 /// TODO: fix documentation
 pub enum RolledEffect {
-    /// Kill $1 people in $2 area
-    Kill(EffectStep, Area), // grow abs (negative)
-    /// Damage $1 buildings in $2 area
-    Damage(EffectStep, Area), // build abs (negative)
-    /// Slow tickers $1% each turn for $2 turns in $3 area
-    Riot(EffectStep, Area), // grow, build, gold per
-    /// Boost growth $1% each turn for $2 turns in $3 area
-    Grow(EffectStep, Area), // grow per
-    /// Boost build speed $1% each turn for $2 turns in $3 area
-    Build(EffectStep, Area), // build per
-    /// Boost gold gain $1% each turn for $2 turns with a one-turn $3 boost
-    Gold(EffectStep, EffectStep), // gold per, gold abs
-    /// Add hero $1 to building in $3 area
-    Hero(i32, String, Area),
-    /// Add item worth $1 to building in $3 area
-    Item(f64, items::ItemType, i32, Area),
+    /// Kill $1 (a fraction) of the people in $2 area
+    Kill(f64, Area),
+    /// Damage buildings by $1 (a fraction of their condition) in $2 area
+    Damage(f64, Area),
+    /// Slow tickers to $1 each turn for $2 turns in $3 area
+    Riot(f64, usize, Area), // grow, build, gold per
+    /// Boost growth by $1 for a turn in $2 area
+    Grow(f64, Area), // grow per
+    /// Boost build speed by $1 for a turn in $2 area
+    Build(f64, Area), // build per
+    /// Boost gold gain by $1 each turn for $2 turns with a one-turn $3 bonus
+    Gold(f64, usize, f64), // gold per, gold abs
+    /// Add hero of level $1 and class $2 (if any was listed) to a building
+    /// their class can occupy
+    Hero(i32, Option<String>),
+    /// Add item worth $1 of kind $2 (if any was listed), with a $3 chance of
+    /// each point of magical power, to a building that can keep it
+    Item(f64, Option<String>, f64),
 }
 
 impl RolledEffect {
@@ -65,298 +89,488 @@ impl RolledEffect {
     */
 
     /// Create a new RolledEffect::Kill from the given arguments.
-    fn kill(dead: &str, viralpt: Option<i64>, area: Area) -> RolledEffect {
+    fn kill<R: Rng>(dead: &str, viralpt: Option<i64>, area: Area, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
         let mut ar = area;
-        let roll = Roller::new(dead);
-        let x : i64 = roll.total();
+        let x : i64 = dice::roll(dead, rng)?;
         if let Some(v) = viralpt {
             // if roll beats viral, "boost" the area up
             if x >= v {
                 ar = ar.upgrade()
             }
         }
-        // EffectStep takes a %, so divide by 100
+        // the roll is a %, so divide by 100
         let change = (x as f64 / 100_f64).max(0f64);
-        RolledEffect::Kill(EffectStep::new(change, 1), ar)
+        Ok(RolledEffect::Kill(change, ar))
     }
 
     /// Create a new RolledEffect::Damage from the given arguments.
-    fn damage(crumbled: &str, viralpt: Option<i64>, area: Area) -> RolledEffect {
+    fn damage<R: Rng>(crumbled: &str, viralpt: Option<i64>, area: Area, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
         let mut ar = area;
-        let roll = Roller::new(crumbled);
-        let x : i64 = roll.total();
+        let x : i64 = dice::roll(crumbled, rng)?;
         if let Some(v) = viralpt {
             // if roll beats viral, "boost" the area up
             if x >= v {
                 ar = ar.upgrade()
             }
         }
-        // EffectStep takes a %, so divide by 100
+        // the roll is a %, so divide by 100
         let change = (x as f64 / 100_f64).max(0f64);
-        RolledEffect::Damage(EffectStep::new(change, 1), ar)
+        Ok(RolledEffect::Damage(change, ar))
     }
 
     /// Create a new RolledEffect::Riot from the given arguments.
-    fn riot(steps: &str, prod: f64, area: Area) -> RolledEffect {
-        let roll = Roller::new(steps);
-        let x : i64 = roll.total();
-        RolledEffect::Riot(EffectStep::new(prod, x as usize), area)
+    fn riot<R: Rng>(steps: &str, prod: f64, area: Area, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
+        let x : i64 = dice::roll(steps, rng)?;
+        Ok(RolledEffect::Riot(prod, x.max(0) as usize, area))
     }
 
     /// Create a new RolledEffect::Grow from the given arguments.
-    fn grow(bonus: &str, area: Area) -> RolledEffect {
-        let roll = Roller::new(bonus);
-        let x : i64 = roll.total();
+    fn grow<R: Rng>(bonus: &str, area: Area, rng: &mut R) -> Result<RolledEffect, DiceError> {
+        let x : i64 = dice::roll(bonus, rng)?;
         // divide by 100, add 100% to create boost
         let change = (x as f64 / 100_f64).max(0f64) + 1f64;
-        RolledEffect::Grow(EffectStep::new(change, 1), area)
+        Ok(RolledEffect::Grow(change, area))
     }
 
     /// Create a new RolledEffect::Build from the given arguments.
-    fn build(bonus: &str, area: Area) -> RolledEffect {
-        let roll = Roller::new(bonus);
-        let x : i64 = roll.total();
+    fn build<R: Rng>(bonus: &str, area: Area, rng: &mut R) -> Result<RolledEffect, DiceError> {
+        let x : i64 = dice::roll(bonus, rng)?;
         // divide by 100, add 100% to create boost
         let change = (x as f64 / 100_f64).max(0f64) + 1f64;
-        RolledEffect::Build(EffectStep::new(change, 1), area)
+        Ok(RolledEffect::Build(change, area))
     }
 
     /// Create a new RolledEffect::Gold from the given arguments.
-    fn gold(value: &str, bonus: f64, steps: &str) -> RolledEffect {
-        let mut roll = Roller::new(steps);
-        let stepx : i64 = roll.total();
-        roll = Roller::new(value);
-        let valuex : i64 = roll.total();
+    fn gold<R: Rng>(value: &str, bonus: f64, steps: &str, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
+        let stepx : i64 = dice::roll(steps, rng)?;
+        let valuex : i64 = dice::roll(value, rng)?;
         // first param is % bonus over steps, second param is absolute immediate bonus
-        RolledEffect::Gold(EffectStep::new(bonus, stepx as usize), EffectStep::new(valuex as f64, 1))
+        Ok(RolledEffect::Gold(bonus, stepx.max(0) as usize, valuex as f64))
     }
 
     /// Create a new RolledEffect::Hero from the given arguments.
-    fn hero(level: &str, classes: &[String]) -> RolledEffect {
-        let roll = Roller::new(level);
-        let x : i64 = roll.total();
-        let class = rand::thread_rng().choose(&classes)
-            .expect("Hero provided without any possible classes!");
-        //TODO: replace with proper, class-based building choice
-        let bldgqs = match class.as_str() {
-            "Cleric" | "Druid" | "Monk" => vec![QType::Residential, QType::Port],
-            "Fighter" | "Assassin" => vec![QType::Port, QType::Administrative],
-            "Paladin" | "Ranger" => vec![QType::Residential, QType::Port, QType::Administrative],
-            "Mage" | "Illusionist" => vec![QType::Academic],
-            "Thief" => vec![QType::Industrial, QType::Port, QType::Administrative],
-            "Bard" => vec![QType::Residential, QType::Academic],
-            _ => vec![], //FIXME: dangerous!
-        };
-        RolledEffect::Hero(x as i32, class.clone(), Area::Building(bldgqs))
+    fn hero<R: Rng>(level: &str, classes: &[String], rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
+        let x : i64 = dice::roll(level, rng)?;
+        let class = rng.choose(classes).cloned();
+        // the manager chooses a headquarters using the class's buildings
+        Ok(RolledEffect::Hero(x as i32, class))
     }
 
     /// Create a new RolledEffect::Item from the given arguments.
-    fn item(value: &str, kind: &[String], magical: f64) -> RolledEffect {
-        let roll = Roller::new(value);
-        let x : i64 = roll.total();
-        let mut pow = 0i32;
-        if magical < 1.0f64 {
-            // take the inverse of magical and compute a bool with a 1 in 1/magical chance
-            // this is the same as checking if a random number between 1 and 100 is less
-            // than magical.
-            while rand::thread_rng().gen_weighted_bool(magical.recip() as u32) && pow < 6 {
-                // keep increasing the power level as long as the rolls succeed
-                pow += 1;
-            }
-        } else {
-            // if the item is guaranteed magical, set it to the maximum level
-            pow = 6;
-        }
-        let kind = rand::thread_rng().choose(&kind)
-            .expect("Item provided without any possible kinds!");
-        let itemtype : items::ItemType = kind.parse()
-            .expect("Kind of item not a valid choice!");
-        let bldgqs = match itemtype {
-            items::ItemType::Book | items::ItemType::HolyRelic => vec![QType::Academic],
-            items::ItemType::Art => vec![QType::Residential, QType::Academic],
-            items::ItemType::Magic => QType::get_qtypes(true),  // all qtypes as vec
-            //weapons and armour
-            _ => vec![QType::Industrial, QType::Port, QType::Administrative],
-        };
-        RolledEffect::Item(x as f64, itemtype, pow, Area::Building(bldgqs))
+    /// The item's power is rolled by the manager from its kind
+    /// (see ItemKind::roll_power).
+    fn item<R: Rng>(value: &str, kind: &[String], magical: f64, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
+        let x : i64 = dice::roll(value, rng)?;
+        let kind = rng.choose(kind).cloned();
+        Ok(RolledEffect::Item(x as f64, kind, magical))
     }
 
 }
 
-/// A struct implementing Iterator to return effect steps.
-/// Can be combined or chained with other EffectStep structs
-/// to produce a varied series of boosts.
-///
-/// ```
-/// use podesta::effects::EffectStep;
-///
-/// let e = EffectStep::new(1.5, 4);
-/// assert!(e.next(), Some(1.5));
-/// assert!(e.next(), Some(1.5));
-/// let f = EffectStep::new(2, 1);
-/// e.combine(f);
-/// assert!(e.next(), Some(3));
-/// assert!(e.next(), Some(1.5));
-/// assert!(e.next(), None);
-/// ```
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct EffectStep {
-    steps: Vec<f64>,
+/// The part of an area's production that a modifier changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Grow,
+    Build,
+    Gold,
 }
 
-enum CombineOp {
+/// How a modifier is combined with the others on the same target.
+/// Multiplicative modifiers are inherited by every area within their own
+/// (Sett -> Quarter -> Building), while additive modifiers only apply to
+/// the area they were added to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Combine {
     Add,
     Mul,
 }
 
-impl EffectStep {
-    pub fn new(boost: f64, nsteps: usize) -> EffectStep {
-        EffectStep {
-            steps: vec![boost; nsteps],
+/// A named modifier on an area's growth, build speed or gold,
+/// lasting a number of steps.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Modifier {
+    /// The name of the modifier (e.g. "Riot").
+    pub name: String,
+    /// What caused the modifier (e.g. the event "Strike").
+    pub source: String,
+    pub target: Target,
+    pub combine: Combine,
+    pub value: f64,
+    /// The number of steps the modifier has left.
+    pub steps: usize,
+}
+
+impl Modifier {
+    pub fn new(name: &str, source: &str, target: Target, combine: Combine,
+               value: f64, steps: usize) -> Modifier
+    {
+        Modifier {
+            name: name.to_string(),
+            source: source.to_string(),
+            target,
+            combine,
+            value,
+            steps,
         }
     }
+}
 
-    /// Take two overlapping EffectSteps and perform the op on each step in other with self.
-    /// The new EffectStep is the length of the longer of self and other: additional
-    /// elements (past the length of the shorter EffectStep) are appended as-is.
-    fn combine(self, other: EffectStep, op: CombineOp) -> EffectStep {
-        let v = if self.steps.len() > other.steps.len() {
-            let mut v_part = self.steps.iter().zip(other.steps.iter())
-                .map(|(x, y)| match op {
-                    CombineOp::Add => *x + *y,
-                    CombineOp::Mul => *x * *y,
-                }).collect::<Vec<_>>();
-            v_part.extend_from_slice(&self.steps[other.steps.len()..]);
-            v_part
-        } else if other.steps.len() > self.steps.len() {
-            let mut v_part = other.steps.iter().zip(self.steps.iter())
-                .map(|(x, y)| match op {
-                    CombineOp::Add => *x + *y,
-                    CombineOp::Mul => *x * *y,
-                }).collect::<Vec<_>>();
-            v_part.extend_from_slice(&other.steps[self.steps.len()..]);
-            v_part
-        } else {
-            self.steps.iter().zip(other.steps.iter())
-                .map(|(x, y)| match op {
-                    CombineOp::Add => *x + *y,
-                    CombineOp::Mul => *x * *y,
-                }).collect::<Vec<_>>()
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match self.combine {
+            Combine::Add => format!("{:+.2}", self.value),
+            Combine::Mul => format!("x{:.2}", self.value),
         };
-        EffectStep {
-            steps: v,
-        }
+        write!(f, "{} ({}): {} {:?} for {} more step(s)",
+               self.name, self.source, value, self.target, self.steps)
     }
 }
 
-impl Add for EffectStep {
-    type Output = EffectStep;
-    fn add(self, other: EffectStep) -> EffectStep {
-        self.combine(other, CombineOp::Add)
-    }
+/// The multiplier and bonus on one target, resolved for the current step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boost {
+    pub mul: f64,
+    pub add: f64,
 }
 
-impl AddAssign for EffectStep {
-    fn add_assign(&mut self, other: EffectStep) {
-        *self = (self.clone()).combine(other, CombineOp::Add);
+impl Boost {
+    /// Apply the boost to the given value.
+    pub fn apply(&self, x: f64) -> f64 {
+        x * self.mul + self.add
     }
 }
 
-impl MulAssign for EffectStep {
-    fn mul_assign(&mut self, other: EffectStep) {
-        *self = (self.clone()).combine(other, CombineOp::Mul);
+impl default::Default for Boost {
+    fn default() -> Boost {
+        Boost { mul: 1.0, add: 0.0 }
     }
 }
 
-impl Mul for EffectStep {
-    type Output = EffectStep;
-    fn mul(self, other: EffectStep) -> EffectStep {
-        self.combine(other, CombineOp::Mul)
-    }
+/// The resolved boosts on each target of an area for the current step.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Boosts {
+    pub grow: Boost,
+    pub build: Boost,
+    pub gold: Boost,
 }
 
-impl Iterator for EffectStep {
-    type Item = f64;
+/// The modifiers active on an area (a Sett, Quarter or Building).
+/// Modifiers are resolved fresh each step (see resolve) and expire once
+/// their duration runs out (see tick).
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EffectFlags {
+    pub mods: Vec<Modifier>,
+}
+
+impl EffectFlags {
+    /// Add a modifier. Modifiers lasting zero steps are ignored.
+    pub fn add(&mut self, m: Modifier) {
+        if m.steps > 0 {
+            self.mods.push(m);
+        }
+    }
+
+    /// Resolve the boosts on the area for this step, given the boosts
+    /// resolved for the area containing it.
+    pub fn resolve(&self, parent: &Boosts) -> Boosts {
+        let boost = |t: Target, p: &Boost| {
+            self.mods.iter().filter(|m| m.target == t)
+                .fold(Boost { mul: p.mul, add: 0.0 }, |b, m| match m.combine {
+                    Combine::Add => Boost { add: b.add + m.value, ..b },
+                    Combine::Mul => Boost { mul: b.mul * m.value, ..b },
+                })
+        };
+        Boosts {
+            grow: boost(Target::Grow, &parent.grow),
+            build: boost(Target::Build, &parent.build),
+            gold: boost(Target::Gold, &parent.gold),
+        }
+    }
 
-    fn next(&mut self) -> Option<f64> {
-        if self.steps.len() == 0 {
-            None
-        } else {
-            Some(self.steps.remove(0))
+    /// Count down a step on every modifier, removing any that have expired.
+    pub fn tick(&mut self) {
+        for m in self.mods.iter_mut() {
+            m.steps -= 1;
         }
+        self.mods.retain(|m| m.steps > 0);
     }
 }
 
-/// Struct for tracking duration and intensity of non-instant effects.
-/// These include Riot, Grow, Build and Gold.
-/// Struct is created by an event and then passed to the target.
-#[derive(Serialize, Deserialize, Debug)]
-pub struct EffectFlags {
-    pub grow: EffectStep,
-    pub build: EffectStep,
-    pub gold: EffectStep,
-    pub grow_bonus: EffectStep,
-    pub build_bonus: EffectStep,
-    pub gold_bonus: EffectStep,
+/// The kinds of effect, as named in lib/data/events.json.
+pub const EFFECT_KINDS: [&str; 8] =
+    ["Kill", "Damage", "Riot", "Grow", "Build", "Gold", "Hero", "Item"];
+
+/// The type of a field of an effect, used to compose effects by hand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// A dice expression, e.g. "5d6 * 2".
+    Dice,
+    /// A number.
+    Number,
+    /// An optional whole number, left blank for none.
+    Viral,
+    /// A comma-separated list of names.
+    Names,
+    /// An area, left blank for the default: a name (e.g. "Source"), or
+    /// JSON as in lib/data/events.json (e.g. {"Building": ["Theatre"]}).
+    Area,
 }
 
-impl EffectFlags {
-    pub fn new(gw: EffectStep, bu: EffectStep, gd: EffectStep,
-               pb: EffectStep, bb: EffectStep, gb: EffectStep) -> EffectFlags
-    {
-        EffectFlags {
-            grow: gw,
-            build: bu,
-            gold: gd,
-            grow_bonus: pb,
-            build_bonus: bb,
-            gold_bonus: gb,
+impl Field {
+    /// Return the JSON for the given typed value of the field, or None if
+    /// it is not a valid value.
+    pub fn to_json(self, input: &str) -> Option<String> {
+        let input = input.trim();
+        match self {
+            Field::Dice => dice::stats(input).ok()
+                .and_then(|_| serde_json::to_string(input).ok()),
+            Field::Number => input.parse::<f64>().ok().map(|x| x.to_string()),
+            Field::Viral if input.is_empty() => Some("null".to_string()),
+            Field::Viral => input.parse::<i64>().ok().map(|x| x.to_string()),
+            Field::Names => {
+                let names = input.split(',').map(|n| n.trim())
+                    .filter(|n| !n.is_empty()).collect::<Vec<_>>();
+                if names.is_empty() { None } else { serde_json::to_string(&names).ok() }
+            },
+            Field::Area if input.is_empty() => Some("null".to_string()),
+            Field::Area => {
+                let json = if input.starts_with('{') {
+                    input.to_string()
+                } else {
+                    serde_json::to_string(input).ok()?
+                };
+                serde_json::from_str::<Area>(&json).ok().map(|_| json)
+            },
         }
     }
 }
 
-impl default::Default for EffectFlags {
-    fn default() -> EffectFlags {
-        EffectFlags::new(EffectStep::new(1.0, 1),
-                         EffectStep::new(1.0, 1),
-                         EffectStep::new(1.0, 1),
-                         EffectStep::new(0.0, 1),
-                         EffectStep::new(0.0, 1),
-                         EffectStep::new(0.0, 1))
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Field::Dice => write!(f, "dice, e.g. 5d6 * 2"),
+            Field::Number => write!(f, "number"),
+            Field::Viral => write!(f, "whole number, or blank for none"),
+            Field::Names => write!(f, "names separated by commas"),
+            Field::Area => write!(f, "e.g. Source or {{\"Building\": [\"Theatre\"]}}, \
+                                      or blank for the default"),
+        }
     }
 }
 
+/// An effect of an event. Effects on an area which leave it out default to
+/// the area around the event's origin (see Effect::default_area).
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Effect {
-    Kill { dead: String, viralpt: Option<i64>, area: Area },
-    Damage { crumbled: String, viralpt: Option<i64>, area: Area },
-    Riot { steps: String, prod: f64, area: Area },
-    Grow { bonus: String, area: Area },
-    Build { bonus: String, area: Area },
+    Kill { dead: String, viralpt: Option<i64>, area: Option<Area> },
+    Damage { crumbled: String, viralpt: Option<i64>, area: Option<Area> },
+    Riot { steps: String, prod: f64, area: Option<Area> },
+    Grow { bonus: String, area: Option<Area> },
+    Build { bonus: String, area: Option<Area> },
     Gold { value: String, bonus: f64, steps: String },
     Hero { level: String, classes: Vec<String> },
     Item { value: String, kind: Vec<String>, magical: f64 },
 }
 
 impl Effect {
-    pub fn activate(&self) -> RolledEffect {
+    /// Return the area the effect applies to, if it has one.
+    pub fn area(&self) -> Option<&Area> {
         match *self {
-            Effect::Kill { ref dead, viralpt, ref area } =>
-                RolledEffect::kill(dead, viralpt, area.clone()),
-            Effect::Damage { ref crumbled, viralpt, ref area } =>
-                RolledEffect::damage(crumbled, viralpt, area.clone()),
-            Effect::Riot { ref steps, prod, ref area } =>
-                RolledEffect::riot(steps, prod, area.clone()),
-            Effect::Grow { ref bonus, ref area } =>
-                RolledEffect::grow(bonus, area.clone()),
-            Effect::Build { ref bonus, ref area } =>
-                RolledEffect::build(bonus, area.clone()),
+            Effect::Kill { ref area, .. } | Effect::Damage { ref area, .. }
+            | Effect::Riot { ref area, .. } | Effect::Grow { ref area, .. }
+            | Effect::Build { ref area, .. } => area.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Return the kind of the effect, as named in EFFECT_KINDS.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Effect::Kill { .. } => "Kill",
+            Effect::Damage { .. } => "Damage",
+            Effect::Riot { .. } => "Riot",
+            Effect::Grow { .. } => "Grow",
+            Effect::Build { .. } => "Build",
+            Effect::Gold { .. } => "Gold",
+            Effect::Hero { .. } => "Hero",
+            Effect::Item { .. } => "Item",
+        }
+    }
+
+    /// Return the dice expressions of the effect, each with its field.
+    pub fn dice(&self) -> Vec<(&'static str, &str)> {
+        match *self {
+            Effect::Kill { ref dead, .. } => vec![("dead", dead)],
+            Effect::Damage { ref crumbled, .. } => vec![("crumbled", crumbled)],
+            Effect::Riot { ref steps, .. } => vec![("steps", steps)],
+            Effect::Grow { ref bonus, .. } | Effect::Build { ref bonus, .. } => vec![("bonus", bonus)],
+            Effect::Gold { ref value, ref steps, .. } => vec![("value", value), ("steps", steps)],
+            Effect::Hero { ref level, .. } => vec![("level", level)],
+            Effect::Item { ref value, .. } => vec![("value", value)],
+        }
+    }
+
+    /// Return the fields of the named kind of effect, with their types.
+    pub fn fields(kind: &str) -> &'static [(&'static str, Field)] {
+        match kind {
+            "Kill" => &[("dead", Field::Dice), ("viralpt", Field::Viral), ("area", Field::Area)],
+            "Damage" =>
+                &[("crumbled", Field::Dice), ("viralpt", Field::Viral), ("area", Field::Area)],
+            "Riot" => &[("steps", Field::Dice), ("prod", Field::Number), ("area", Field::Area)],
+            "Grow" | "Build" => &[("bonus", Field::Dice), ("area", Field::Area)],
+            "Gold" => &[("value", Field::Dice), ("bonus", Field::Number), ("steps", Field::Dice)],
+            "Hero" => &[("level", Field::Dice), ("classes", Field::Names)],
+            "Item" => &[("value", Field::Dice), ("kind", Field::Names), ("magical", Field::Number)],
+            _ => &[],
+        }
+    }
+
+    /// Return the area struck by the effect when it gives none: the
+    /// building where the event happened for Damage, or its quarter
+    /// otherwise. Without a known origin, a random quarter is struck.
+    pub fn default_area(&self, has_origin: bool) -> Area {
+        match *self {
+            Effect::Damage { .. } if has_origin => Area::Source,
+            _ if has_origin => Area::SourceQuarter,
+            _ => Area::Quarter(vec![]),
+        }
+    }
+
+    /// Roll the effect's dice using the given generator, for an event whose
+    /// origin is known or not (see default_area).
+    /// Return an error if any of its dice cannot be rolled.
+    pub fn activate<R: Rng>(&self, has_origin: bool, rng: &mut R)
+        -> Result<RolledEffect, DiceError>
+    {
+        let area = self.area().cloned().unwrap_or_else(|| self.default_area(has_origin));
+        match *self {
+            Effect::Kill { ref dead, viralpt, .. } =>
+                RolledEffect::kill(dead, viralpt, area, rng),
+            Effect::Damage { ref crumbled, viralpt, .. } =>
+                RolledEffect::damage(crumbled, viralpt, area, rng),
+            Effect::Riot { ref steps, prod, .. } =>
+                RolledEffect::riot(steps, prod, area, rng),
+            Effect::Grow { ref bonus, .. } =>
+                RolledEffect::grow(bonus, area, rng),
+            Effect::Build { ref bonus, .. } =>
+                RolledEffect::build(bonus, area, rng),
             Effect::Gold { ref value, bonus, ref steps } =>
-                RolledEffect::gold(value, bonus, steps),
+                RolledEffect::gold(value, bonus, steps, rng),
             Effect::Hero { ref level, ref classes } =>
-                RolledEffect::hero(level, classes),
+                RolledEffect::hero(level, classes, rng),
             Effect::Item { ref value, ref kind, magical } =>
-                RolledEffect::item(value, kind, magical),
+                RolledEffect::item(value, kind, magical, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng;
+
+    fn flags(mods: Vec<Modifier>) -> EffectFlags {
+        let mut f = EffectFlags::default();
+        for m in mods {
+            f.add(m);
         }
+        f
+    }
+
+    fn gold(combine: Combine, value: f64, steps: usize) -> Modifier {
+        Modifier::new("Test", "the test", Target::Gold, combine, value, steps)
+    }
+
+    #[test]
+    fn multipliers_are_inherited_by_inner_areas() {
+        let sett = flags(vec![gold(Combine::Mul, 0.5, 1)]).resolve(&Boosts::default());
+        let quarter = flags(vec![gold(Combine::Mul, 3.0, 1)]).resolve(&sett);
+        let building = EffectFlags::default().resolve(&quarter);
+        assert_eq!(sett.gold.mul, 0.5);
+        assert_eq!(quarter.gold.mul, 1.5);
+        assert_eq!(building.gold.mul, 1.5);
+        // other targets are untouched
+        assert_eq!(building.build, Boost::default());
+    }
+
+    #[test]
+    fn bonuses_stay_on_their_own_area() {
+        let sett = flags(vec![gold(Combine::Add, 10.0, 1),
+                              gold(Combine::Add, 5.0, 1)]).resolve(&Boosts::default());
+        let quarter = EffectFlags::default().resolve(&sett);
+        assert_eq!(sett.gold.add, 15.0);
+        assert_eq!(quarter.gold.add, 0.0);
+        assert_eq!(quarter.gold.apply(100.0), 100.0);
+    }
+
+    #[test]
+    fn modifiers_expire_once_their_steps_run_out() {
+        let mut f = flags(vec![gold(Combine::Mul, 2.0, 1),
+                               gold(Combine::Add, 5.0, 2),
+                               gold(Combine::Add, 1.0, 0)]);
+        // modifiers lasting no steps are never added
+        assert_eq!(f.mods.len(), 2);
+        f.tick();
+        assert_eq!(f.mods.len(), 1);
+        assert_eq!(f.mods[0].steps, 1);
+        assert_eq!(f.resolve(&Boosts::default()).gold, Boost { mul: 1.0, add: 5.0 });
+        f.tick();
+        assert!(f.mods.is_empty());
+    }
+
+    #[test]
+    fn effects_default_to_where_their_event_happened() {
+        let damage = Effect::Damage { crumbled: String::from("1d6"), viralpt: None, area: None };
+        let riot = Effect::Riot { steps: String::from("1d6"), prod: 0.5, area: None };
+        assert!(enum_match!(damage.default_area(true), Area::Source));
+        assert!(enum_match!(riot.default_area(true), Area::SourceQuarter));
+        // without an origin, any one quarter is struck
+        let any_quarter = |a: Area| match a {
+            Area::Quarter(names) => names.is_empty(),
+            _ => false,
+        };
+        assert!(any_quarter(damage.default_area(false)));
+        assert!(any_quarter(riot.default_area(false)));
+        // an area given by the effect is kept
+        let mut rng = rng::SimRng::new(1);
+        let named = Effect::Grow { bonus: String::from("1d6"), area: Some(Area::Adjacent) };
+        assert!(enum_match!(named.activate(true, &mut rng).unwrap(),
+                            RolledEffect::Grow(_, Area::Adjacent)));
+    }
+
+    #[test]
+    fn dice_fields_must_be_rollable() {
+        assert_eq!(Field::Dice.to_json("2d6 + 3"), Some(String::from("\"2d6 + 3\"")));
+        assert_eq!(Field::Dice.to_json("1d0"), None);
+        assert_eq!(Field::Dice.to_json("6/0"), None);
+        assert_eq!(Field::Dice.to_json("2d"), None);
+    }
+
+    #[test]
+    fn heroes_and_items_with_nothing_listed_roll_none() {
+        let mut rng = rng::SimRng::new(1);
+        let hero = Effect::Hero { level: String::from("1"), classes: vec![] };
+        assert!(enum_match!(hero.activate(false, &mut rng), Ok(RolledEffect::Hero(1, None))));
+        let item = Effect::Item { value: String::from("1"), kind: vec![], magical: 0.0 };
+        assert!(enum_match!(item.activate(false, &mut rng), Ok(RolledEffect::Item(_, None, _))));
+    }
+
+    #[test]
+    fn effects_with_bad_dice_fail_to_activate() {
+        let mut rng = rng::SimRng::new(1);
+        let bad = Effect::Build { bonus: String::from("1d0"), area: None };
+        assert!(enum_match!(bad.activate(false, &mut rng), Err(DiceError::NoSides)));
     }
 }
@@ -14,6 +14,10 @@
 //!     desc: "%s has erupted into flames!".to_string(),
 //!     chance: 3,
 //!     effects: vec!(),
+//!     conditions: events::Conditions::default(),
+//!     cooldown: 0,
+//!     exclusive: vec!(),
+//!     follow_ups: vec!(),
 //! };
 //! let se = serde_json::to_string(&ev).unwrap();
 //! let de : events::Event = serde_json::from_str(&se).unwrap();
@@ -21,9 +25,15 @@
 //! assert_eq!(de.id, ev.id)
 //! # }
 //! ```
+use dice;
 use effects;
-use rand::{self, Rng};
-use std::collections::{VecDeque, HashMap};
+use people;
+use sett;
+use rand::Rng;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
 
 /// A struct representing an event that occurs in a quarter.
@@ -37,88 +47,392 @@ pub struct Event {
     pub desc: String,
     pub chance: i32,
     pub effects: Vec<effects::Effect>,
+    /// The conditions the sett must meet for the event to fire.
+    #[serde(default)]
+    pub conditions: Conditions,
+    /// The number of steps after firing during which the event cannot
+    /// fire again.
+    #[serde(default)]
+    pub cooldown: i32,
+    /// The exclusion groups of the event. At most one event of each group
+    /// fires on a step.
+    #[serde(default)]
+    pub exclusive: Vec<String>,
+    /// The events which may follow this one once it is activated.
+    #[serde(default)]
+    pub follow_ups: Vec<FollowUp>,
 }
 
-/// A ring buffer of events.
+/// An event which may follow another, where the other happened.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FollowUp {
+    /// The name of the following event.
+    pub event: String,
+    /// The chance (in %) that the event follows.
+    pub chance: f64,
+    /// The number of steps (at least 1) after the activation of the first
+    /// event until the following event fires.
+    pub delay: i32,
+}
+
+/// The conditions under which an event may fire. Each is met by default.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Conditions {
+    /// The minimum population of the sett.
+    #[serde(default)]
+    pub min_pop: f64,
+    /// Buildings (by name or type) of which the sett must have one in use.
+    #[serde(default)]
+    pub buildings: Vec<String>,
+    /// Whether the sett must be without a governor.
+    #[serde(default)]
+    pub no_governor: bool,
+    /// The seasons in which the event may fire (any if empty).
+    #[serde(default)]
+    pub seasons: Vec<sett::Season>,
+}
+
+impl Conditions {
+    /// Return true if the given sett meets every condition.
+    pub fn hold(&self, s: &sett::Sett) -> bool {
+        let standing = |name: &String| s.get_buildings().iter().any(|(_, b)| {
+            let b = b.borrow();
+            (b.plan.name == *name || b.plan.btype == *name) && b.cond.is_in_use()
+        });
+        s.pop >= self.min_pop
+            && self.buildings.iter().all(standing)
+            && (!self.no_governor || s.get_governors().is_empty())
+            && (self.seasons.is_empty() || self.seasons.contains(&s.season()))
+    }
+}
+
+/// Where an event happened: the building whose chances fired it and the
+/// quarter it stands in. Kept by name so that it survives saving.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub quarter: String,
+    pub building: String,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the {} in the {} Quarter", self.building, self.quarter)
+    }
+}
+
+/// An event due to be performed, with where it happened (if known).
 #[derive(Debug, Serialize, Deserialize)]
-pub struct EventQueue {
-    events: VecDeque<Rc<Event>>,
+pub struct Scheduled {
+    pub event: Rc<Event>,
+    pub origin: Option<Origin>,
+    /// For a follow-up, the name of the event it follows and the step on
+    /// which that event was performed.
+    pub after: Option<(String, i32)>,
 }
 
-impl EventQueue {
-    /// Create an empty EventQueue with capacity cap.
-    pub fn new(cap: usize) -> Self {
-        EventQueue { events: VecDeque::with_capacity(cap) }
+/// The events due on each coming step of the settlement. Events due on the
+/// same step are performed in the order they were scheduled.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Schedule {
+    steps: BTreeMap<i32, Vec<Scheduled>>,
+}
+
+impl Schedule {
+    /// Create an empty Schedule.
+    pub fn new() -> Self {
+        Schedule::default()
     }
 
-    /// Push an Event onto the end of the queue. If it is full, pop the first
-    /// element beforehand.
-    pub fn push(&mut self, e: Rc<Event>) {
-        if self.is_full() {
-            //TODO: should we really just toss an event here? how to handle a maxed queue?
-            self.pop();
-        }
-        self.events.push_back(e)
+    /// Schedule an event to be performed on the given step.
+    pub fn add(&mut self, step: i32, s: Scheduled) {
+        self.steps.entry(step).or_default().push(s)
     }
 
-    /// Pop the first element off the queue.
-    pub fn pop(&mut self) -> Option<Rc<Event>> {
-        self.events.pop_front()
+    /// Remove and return the events due on or before the given step,
+    /// earliest first.
+    pub fn take_due(&mut self, step: i32) -> Vec<Scheduled> {
+        let later = self.steps.split_off(&(step + 1));
+        mem::replace(&mut self.steps, later).into_values().flatten().collect()
     }
+}
 
-    /// Check if the queue is full.
-    fn is_full(&self) -> bool {
-        self.events.capacity() == self.events.len()
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.steps.is_empty() {
+            return write!(f, "No events are scheduled.");
+        }
+        let lines = self.steps.iter().flat_map(|(step, v)| v.iter().map(move |s| {
+            let place = s.origin.as_ref().map(|o| format!(" at {}", o)).unwrap_or_default();
+            match s.after {
+                Some((ref after, n)) =>
+                    format!("Step {}: {}{} (following the {} on step {})",
+                            step, s.event.name, place, after.to_lowercase(), n),
+                None => format!("Step {}: {}{}", step, s.event.name, place),
+            }
+        })).collect::<Vec<_>>();
+        write!(f, "{}", lines.join("\n"))
     }
+}
 
-    /// Return the length of the queue.
-    pub fn len(&self) -> usize {
-        self.events.len()
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} (chance {})", self.name, self.chance)?;
+        writeln!(f, "\"{}\"", self.desc)?;
+        let c = &self.conditions;
+        if c.min_pop > 0.0 {
+            writeln!(f, "Needs a population of at least {}", c.min_pop)?;
+        }
+        if !c.buildings.is_empty() {
+            writeln!(f, "Needs one of: {}", c.buildings.join(", "))?;
+        }
+        if c.no_governor {
+            writeln!(f, "Needs the sett to have no governor")?;
+        }
+        if !c.seasons.is_empty() {
+            let seasons = c.seasons.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            writeln!(f, "Fires only in: {}", seasons.join(", "))?;
+        }
+        if self.cooldown > 0 {
+            writeln!(f, "Cooldown: {} steps", self.cooldown)?;
+        }
+        if !self.exclusive.is_empty() {
+            writeln!(f, "Exclusion groups: {}", self.exclusive.join(", "))?;
+        }
+        for fu in self.follow_ups.iter() {
+            writeln!(f, "May be followed by {} ({}% after {} steps)",
+                     fu.event, fu.chance, fu.delay)?;
+        }
+        write!(f, "Effects:")?;
+        for e in self.effects.iter() {
+            write!(f, "\n  {}", e.kind())?;
+            for (field, expr) in e.dice() {
+                match dice::stats(expr) {
+                    Ok(st) => write!(f, "\n    {}: {} ({})", field, expr, st)?,
+                    Err(err) => write!(f, "\n    {}: {} (invalid: {})", field, expr, err)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Event {
+    /// Return an event of the given name and effects, certain to fire,
+    /// with no conditions, cooldown, exclusion groups or follow-ups.
+    /// Tests override the fields they need (e.g. `cooldown: 2, ..test(..)`).
+    pub fn test(name: &str, effects: Vec<effects::Effect>) -> Event {
+        Event {
+            name: name.to_string(),
+            id: 0,
+            desc: String::from("{} is tested."),
+            chance: 100,
+            effects,
+            conditions: Conditions::default(),
+            cooldown: 0,
+            exclusive: vec![],
+            follow_ups: vec![],
+        }
     }
 }
 
 impl Event {
-    /// Attempt to activate the event's effects.
-    pub fn activate(&self) -> Vec<effects::RolledEffect> {
-        self.effects.iter().map(|e| e.activate()).collect::<Vec<_>>()
+    /// Attempt to activate the event's effects. Effects without an area
+    /// default to the area around the given origin (see Effect::activate).
+    /// Return an error if any effect's dice cannot be rolled.
+    pub fn activate<R: Rng>(&self, origin: Option<&Origin>, rng: &mut R)
+        -> Result<Vec<effects::RolledEffect>, dice::DiceError>
+    {
+        self.effects.iter().map(|e| e.activate(origin.is_some(), rng)).collect()
+    }
+
+    /// Roll for each of the event's follow-ups, returning those which follow.
+    pub fn roll_follow_ups<R: Rng>(&self, rng: &mut R) -> Vec<&FollowUp> {
+        self.follow_ups.iter().filter(|f| rng.gen_range(0f64, 100f64) < f.chance).collect()
+    }
+
+    /// Return true if the event may fire in the given sett: its conditions
+    /// hold and it is not cooling down from the last time it fired.
+    pub fn can_fire(&self, s: &sett::Sett) -> bool {
+        let cooled = s.fired.get(&self.name).map(|&last| s.age - last > self.cooldown);
+        self.conditions.hold(s) && cooled.unwrap_or(true)
+    }
+
+    /// Return the event's description, naming where it happened in place
+    /// of `{}`: the origin if known, or else the sett's name.
+    pub fn describe(&self, origin: Option<&Origin>, sett: &str) -> String {
+        let place = origin.map(|o| o.to_string()).unwrap_or_else(|| sett.to_string());
+        let desc = self.desc.replace("{}", &place);
+        // capitalize the place if it begins the description
+        let mut cs = desc.chars();
+        match cs.next() {
+            Some(c) => c.to_uppercase().chain(cs).collect(),
+            None => desc,
+        }
     }
 }
 
 /// A struct representing the probabilities of each named event
 /// at the current step. Returned by sett::step and used to
 /// infer if an event occurs by the manager.
+/// Events are kept in name order so that rolls are reproducible.
 #[derive(Debug)]
 pub struct EventMap {
     pub step: i32,
-    pub map: HashMap<String, f64>
+    pub map: BTreeMap<String, f64>,
+    /// The chance each building adds to each event, by event name.
+    pub origins: BTreeMap<String, Vec<(Origin, f64)>>,
+    /// Whether a new quarter is founded this step.
+    pub founding: bool,
+    /// The buildings which fell into ruin this step, each with the
+    /// occupants it drove out.
+    pub ruined: Vec<(Origin, Vec<Rc<RefCell<people::Hero>>>)>,
 }
 
 impl EventMap {
     pub fn new(step: i32) -> EventMap {
         EventMap {
             step: step,
-            map: HashMap::new(),
+            map: BTreeMap::new(),
+            origins: BTreeMap::new(),
+            founding: false,
+            ruined: vec![],
         }
     }
 
-    /// Add entries to the map using the given hashmap.
+    /// Add entries to the map using the given hashmap of the chances added
+    /// by the building at the given origin.
     /// Existing entries are incremented, while new entries are simply added.
-    pub fn add_chances(&mut self, ec: HashMap<String, f64>) {
+    pub fn add_chances(&mut self, ec: BTreeMap<String, f64>, origin: Origin) {
         for (event, chance) in ec.into_iter() {
-            *self.map.entry(event).or_insert(0.0) += chance;
+            *self.map.entry(event.clone()).or_insert(0.0) += chance;
+            self.origins.entry(event).or_default().push((origin.clone(), chance));
         }
     }
 
     /// Return a vector of random event keys from the EventMap, based on the
-    /// chance value of the keys.
-    pub fn rand_events(&self) -> Vec<&str> {
+    /// chance value of the keys, each with where it happened: a building
+    /// chosen in proportion to the chance it added.
+    pub fn rand_events<R: Rng>(&self, rng: &mut R) -> Vec<(&str, Option<Origin>)> {
         let mut v = vec![];
         for (event, chance) in self.map.iter() {
-            let r = rand::thread_rng().gen_range(0f64, 100f64);
+            let r = rng.gen_range(0f64, 100f64);
             if r <= *chance {
-                v.push(event.as_str())
+                v.push((event.as_str(), self.rand_origin(event, rng)))
             }
         }
         v
     }
+
+    /// Choose the origin of the named event, weighted by the chance each
+    /// building added to it.
+    fn rand_origin<R: Rng>(&self, event: &str, rng: &mut R) -> Option<Origin> {
+        let origins = self.origins.get(event)?;
+        let total = origins.iter().map(|o| o.1.max(0.0)).sum::<f64>();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut r = rng.gen_range(0f64, total);
+        for o in origins.iter().filter(|o| o.1 > 0.0) {
+            if r < o.1 {
+                return Some(o.0.clone());
+            }
+            r -= o.1;
+        }
+        origins.iter().rev().find(|o| o.1 > 0.0).map(|o| o.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use rng;
+
+    fn event(name: &str, desc: &str) -> Event {
+        Event { desc: desc.to_string(), chance: 0, ..Event::test(name, vec![]) }
+    }
+
+    fn origin(quarter: &str, building: &str) -> Origin {
+        Origin { quarter: quarter.to_string(), building: building.to_string() }
+    }
+
+    fn chances(event: &str, chance: f64) -> BTreeMap<String, f64> {
+        let mut ec = BTreeMap::new();
+        ec.insert(event.to_string(), chance);
+        ec
+    }
+
+    #[test]
+    fn descriptions_name_where_the_event_happened() {
+        let fire = event("Fire", "{} has erupted into flames!");
+        assert_eq!(fire.describe(Some(&origin("Smiths'", "Forge")), "Testville"),
+                   "The Forge in the Smiths' Quarter has erupted into flames!");
+        assert_eq!(fire.describe(None, "Testville"), "Testville has erupted into flames!");
+    }
+
+    #[test]
+    fn origins_are_chosen_by_the_chance_they_added() {
+        let mut rng = rng::SimRng::new(1);
+        let mut map = EventMap::new(1);
+        map.add_chances(chances("Fire", 100.0), origin("Smiths'", "Forge"));
+        map.add_chances(chances("Fire", 0.0), origin("Smiths'", "Mint"));
+        map.add_chances(chances("Flood", 0.0), origin("Docks", "Pier"));
+        assert_eq!(map.map["Fire"], 100.0);
+        for _ in 0..20 {
+            assert_eq!(map.rand_origin("Fire", &mut rng), Some(origin("Smiths'", "Forge")));
+        }
+        // an event with no chance anywhere has no origin
+        assert_eq!(map.rand_origin("Flood", &mut rng), None);
+        assert_eq!(map.rand_origin("Plague", &mut rng), None);
+        let fired = map.rand_events(&mut rng);
+        assert_eq!(fired, vec![("Fire", Some(origin("Smiths'", "Forge")))]);
+    }
+
+    fn scheduled(name: &str, after: Option<(&str, i32)>) -> Scheduled {
+        Scheduled {
+            event: Rc::new(event(name, "{} is tested.")),
+            origin: Some(origin("Smiths'", "Forge")),
+            after: after.map(|(e, n)| (e.to_string(), n)),
+        }
+    }
+
+    fn names(due: &[Scheduled]) -> Vec<&str> {
+        due.iter().map(|d| d.event.name.as_str()).collect()
+    }
+
+    #[test]
+    fn scheduled_events_fall_due_on_their_step() {
+        let mut sched = Schedule::new();
+        sched.add(5, scheduled("Fire", None));
+        assert!(sched.take_due(4).is_empty());
+        assert_eq!(names(&sched.take_due(5)), vec!["Fire"]);
+        assert!(sched.take_due(5).is_empty());
+    }
+
+    #[test]
+    fn events_due_together_are_all_kept_in_order() {
+        let mut sched = Schedule::new();
+        sched.add(7, scheduled("Flood", None));
+        sched.add(3, scheduled("Fire", None));
+        sched.add(3, scheduled("Smoke", Some(("Fire", 1))));
+        sched.add(9, scheduled("Plague", None));
+        // overdue events are not lost either
+        assert_eq!(names(&sched.take_due(8)), vec!["Fire", "Smoke", "Flood"]);
+        assert_eq!(names(&sched.take_due(9)), vec!["Plague"]);
+    }
+
+    #[test]
+    fn schedules_survive_saving() {
+        let mut sched = Schedule::new();
+        sched.add(3, scheduled("Fire", None));
+        sched.add(3, scheduled("Smoke", Some(("Fire", 1))));
+        let bytes = bincode::serialize(&sched, bincode::Infinite).unwrap();
+        let mut loaded: Schedule = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.to_string(), sched.to_string());
+        let due = loaded.take_due(3);
+        assert_eq!(names(&due), vec!["Fire", "Smoke"]);
+        assert_eq!(due[1].origin, Some(origin("Smiths'", "Forge")));
+        assert_eq!(due[1].after, Some((String::from("Fire"), 1)));
+    }
 }
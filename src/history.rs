@@ -10,6 +10,18 @@ pub struct History {
 pub struct Entry {
     pub date: i32,
     pub info: String,
+    pub author: Author,
+}
+
+/// Who caused an entry in the history.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Author {
+    /// The simulation of the settlement.
+    Sim,
+    /// The game master, e.g. by triggering an event.
+    GM,
+    /// A command only available in dev mode.
+    Dev,
 }
 
 impl History {
@@ -20,7 +32,20 @@ impl History {
 
     /// Add an entry to the history with the given date and information.
     pub fn add_entry(&mut self, date: i32, info: String) {
-        self.entries.push(Entry::new(date, info))
+        self.entries.push(Entry::new(date, info, Author::Sim))
+    }
+
+    /// Add an entry to the history with the given date, information and
+    /// author.
+    pub fn add_authored(&mut self, date: i32, info: String, author: Author) {
+        self.entries.push(Entry::new(date, info, author))
+    }
+
+    /// Mark every entry after the first n as caused by the given author.
+    pub fn mark_since(&mut self, n: usize, author: Author) {
+        for e in self.entries.iter_mut().skip(n) {
+            e.author = author;
+        }
     }
 
     /// Return a vector of entries matching the given date.
@@ -39,13 +64,17 @@ impl History {
 }
 
 impl Entry {
-    fn new(date: i32, info: String) -> Entry {
-        Entry { date: date, info: info }
+    fn new(date: i32, info: String, author: Author) -> Entry {
+        Entry { date, info, author }
     }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Step {}: {}", self.date, self.info)
+        match self.author {
+            Author::Sim => write!(f, "Step {}: {}", self.date, self.info),
+            Author::GM => write!(f, "Step {} [GM]: {}", self.date, self.info),
+            Author::Dev => write!(f, "Step {} [dev]: {}", self.date, self.info),
+        }
     }
 }
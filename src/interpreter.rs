@@ -11,14 +11,32 @@ pub enum ParseResult {
     Step(i64),
     /// Create a new object based on the vector.
     New(Option<String>, Option<String>, Option<String>),
-    /// Repair a building.
-    Repair(Option<String>, Option<String>),
+    /// Repair a building, optionally by some points of its condition.
+    Repair(Option<String>, Option<String>, Option<f64>),
+    /// Repair every damaged building within an optional gold budget.
+    RepairAll(Option<f64>),
+    /// Demolish a building.
+    Demolish(Option<String>, Option<String>),
+    /// Promote a hero to govern the sett.
+    Promote(Option<String>),
+    /// Fire an event, optionally at a quarter and building.
+    Trigger(Option<String>, Option<String>, Option<String>),
+    /// Compose and fire an event, optionally at a quarter and building.
+    Custom(Option<String>, Option<String>),
+    /// Dev mode only. Create a new hero or item of the given class or kind
+    /// and level or value.
+    Spawn(Option<String>, Option<String>, Option<String>),
+    /// Dev mode only. Set the sett's gold or population, or the condition
+    /// of a building.
+    Set(Option<String>, Option<String>, Option<String>, Option<String>),
     /// Print the named object to the screen.
     Print(Option<String>, Option<String>, Option<String>),
     /// Save the environment to a file.
     Save(Option<String>),
     /// Load a file into the environment.
     Load(Option<String>),
+    /// Show the random seed, or restart the generator from the given seed.
+    Seed(Option<u64>),
     /// Toggle user prompting.
     ToggleAuto,
     /// Toggle dev mode.
@@ -54,18 +72,28 @@ pub fn parse_input(input: &str) -> ParseResult {
             },
             "commands" => ParseResult::Commands,
             "new" | "add" => ParseResult::New(cmd.next(), cmd.next(), cmd.next()),
-            "rep" => ParseResult::Repair(cmd.next(), cmd.next()),
+            "rep" => match cmd.next() {
+                Some(ref all) if all == "all" =>
+                    ParseResult::RepairAll(cmd.next().and_then(|s| s.parse::<f64>().ok())),
+                bname => ParseResult::Repair(bname, cmd.next(), cmd.next().and_then(|s| {
+                    s.trim_end_matches('%').parse::<f64>().ok()
+                })),
+            },
+            "demolish" => ParseResult::Demolish(cmd.next(), cmd.next()),
+            "promote" => ParseResult::Promote(cmd.next()),
+            "trigger" => ParseResult::Trigger(cmd.next(), cmd.next(), cmd.next()),
+            "custom" => ParseResult::Custom(cmd.next(), cmd.next()),
+            "spawn" => ParseResult::Spawn(cmd.next(), cmd.next(), cmd.next()),
+            "set" => ParseResult::Set(cmd.next(), cmd.next(), cmd.next(), cmd.next()),
             "step" | "n" | "next" =>
                 ParseResult::Step(cmd.next().and_then(|s| s.parse::<i64>().ok()).unwrap_or(1)),
             "p" | "print" => ParseResult::Print(cmd.next(), cmd.next(), cmd.next()),
+            "seed" => ParseResult::Seed(cmd.next().and_then(|s| s.parse::<u64>().ok())),
             "a" | "auto" => ParseResult::ToggleAuto,
             "q" | "quit" => ParseResult::Quit,
             "sv" | "save" => ParseResult::Save(cmd.next()),
             "ld" | "load" => ParseResult::Load(cmd.next()),
-            "dev" => ParseResult::ToggleDev, //TODO: add dev mode
-                                           //TODO: allow normally blocked commands
-                                           //TODO: e.g. add building w/o matching quarter or gold,
-                                           //TODO: create a hero or perform an event's effects
+            "dev" => ParseResult::ToggleDev,
             "" => ParseResult::Success,
             s @ _ => ParseResult::Unknown(s.to_string()),
         },
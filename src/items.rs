@@ -1,4 +1,7 @@
 use std::fmt;
+use std::rc::Rc;
+use buildings::BuildingPlan;
+use rand::Rng;
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
@@ -6,14 +9,14 @@ use std::fmt;
 pub struct Item {
     pub name: String,
     pub age: i32,
-    pub kind: ItemType,
+    pub kind: Rc<ItemKind>,
     /// The magical power of the item: 0 if non-magical, up to +6 otherwise.
     pub power: i32,
     pub worth: f64,
 }
 
 impl Item {
-    pub fn new(n: &str, k: ItemType, p: i32, w: f64) -> Item {
+    pub fn new(n: &str, k: Rc<ItemKind>, p: i32, w: f64) -> Item {
         Item {
             name: n.to_string(),
             age: 0,
@@ -30,27 +33,60 @@ impl Item {
         //TODO: placeholder increment
     }
 
-    /// Collect gold. Return a % of the item's worth based on its power.
+    /// Collect gold. Return a % of the item's worth based on its power
+    /// and the income rate of its kind.
     pub fn collect_gold(&self) -> f64 {
-        self.worth * (0.01 * self.power as f64)
+        self.worth * (self.kind.income * self.power as f64)
     }
 }
 
-macro_attr! {
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq,
-         IterVariants!(ItemTypeVariants),
-         IterVariantNames!(ItemTypeVariantNames),
-         EnumDisplay!, EnumFromStr!)]
-    /// An enum representing common types of Item.
-    pub enum ItemType {
-        Book,
-        Art,
-        HolyRelic,
-        Magic,
-        LightArmour,
-        HeavyArmour,
-        LightWeapon,
-        HeavyWeapon,
+#[derive(Debug, Serialize, Deserialize)]
+/// A kind of Item, and the rules for where items of that kind are kept.
+/// Generated from lib/data/items.json
+pub struct ItemKind {
+    pub name: String,
+    pub desc: String,
+    /// The building plans or building types (see BuildingPlan::btype) that
+    /// can keep items of this kind, or "ALL" for any building.
+    pub buildings: Vec<String>,
+    /// Multiplier on the chance of each successive point of magical power.
+    pub magic: f64,
+    /// The greatest magical power an item of this kind can have.
+    pub max_power: i32,
+    /// Fraction of the item's worth collected in gold per point of power.
+    pub income: f64,
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl ItemKind {
+    /// Return true if buildings of the given plan can keep items of this kind.
+    pub fn allows(&self, plan: &BuildingPlan) -> bool {
+        self.buildings.iter().any(|b| b == "ALL" || *b == plan.name || *b == plan.btype)
+    }
+
+    /// Roll the magical power of a new item of this kind, given the chance
+    /// (between 0 and 1) of each successive point of power.
+    /// A chance of 1 or more always gives the maximum power.
+    pub fn roll_power<R: Rng>(&self, magical: f64, rng: &mut R) -> i32 {
+        let chance = magical * self.magic;
+        if chance >= 1.0 {
+            return self.max_power;
+        }
+        let mut pow = 0;
+        if chance > 0.0 {
+            // take the inverse of the chance and compute a bool with a
+            // 1 in 1/chance chance, increasing the power level as long as
+            // the rolls succeed
+            while pow < self.max_power && rng.gen_weighted_bool(chance.recip() as u32) {
+                pow += 1;
+            }
+        }
+        pow
     }
 }
 
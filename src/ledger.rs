@@ -0,0 +1,185 @@
+//! A ledger of the settlement's income and spending.
+//! Only the entries for the last few steps are kept.
+
+use std::collections::BTreeMap;
+
+macro_attr! {
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq,
+         IterVariants!(CategoryVariants),
+         EnumDisplay!)]
+    /// A category of income or spending in the treasury.
+    pub enum Category {
+        /// Gold collected from the population.
+        Tax,
+        /// Gold brought in by buildings and their occupants.
+        Buildings,
+        /// Gold brought in by items kept in buildings.
+        Items,
+        /// Gold returned by heroes from their adventures.
+        Treasure,
+        /// Gold given or taken by events.
+        Events,
+        /// Gold granted for founding a new quarter.
+        Founding,
+        /// Gold spent constructing or demolishing buildings.
+        Construction,
+        /// Gold spent repairing or restoring buildings.
+        Repairs,
+        /// Gold spent maintaining buildings.
+        Upkeep,
+        /// Gold added or removed by hand in dev mode.
+        Adjustments,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub date: i32,
+    pub category: Category,
+    /// The quarter the gold came from or went to, if any.
+    pub quarter: Option<String>,
+    /// Positive for income, negative for spending.
+    pub amount: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ledger {
+    pub entries: Vec<Entry>,
+    /// Number of steps of entries to keep.
+    pub steps: i32,
+}
+
+impl Ledger {
+    /// Create a new Ledger keeping the given number of steps of entries.
+    pub fn new(steps: i32) -> Ledger {
+        Ledger { entries: vec!(), steps }
+    }
+
+    /// Record an amount of gold (negative for spending) in the given
+    /// category on the given date, dropping any entries too old to keep.
+    pub fn record(&mut self, date: i32, category: Category, quarter: Option<String>,
+                  amount: f64)
+    {
+        if amount == 0.0 {
+            return;
+        }
+        self.entries.push(Entry { date, category, quarter, amount });
+        let oldest = date - self.steps;
+        self.entries.retain(|e| e.date > oldest);
+    }
+
+    /// Total the income and spending in each category and each quarter
+    /// over the given number of steps up to the given date (or every step
+    /// kept if None).
+    pub fn totals(&self, date: i32, steps: Option<i32>) -> Totals {
+        let steps = steps.unwrap_or(self.steps).max(1).min(self.steps);
+        let from = date - steps + 1;
+        let entries = self.entries.iter()
+            .filter(|e| e.date >= from && e.date <= date);
+        // sum income and spending separately
+        let add = |totals: &mut (f64, f64), amount: f64| {
+            if amount > 0.0 { totals.0 += amount } else { totals.1 -= amount }
+        };
+        let mut t = Totals {
+            from,
+            categories: Category::iter_variants().map(|c| (c, (0.0, 0.0))).collect(),
+            quarters: BTreeMap::new(),
+            total: (0.0, 0.0),
+        };
+        for e in entries {
+            if let Some(c) = t.categories.iter_mut().find(|c| c.0 == e.category) {
+                add(&mut c.1, e.amount);
+            }
+            let q = e.quarter.clone().unwrap_or_else(|| String::from("(sett)"));
+            add(t.quarters.entry(q).or_insert((0.0, 0.0)), e.amount);
+            add(&mut t.total, e.amount);
+        }
+        t
+    }
+
+    /// Return a table of the income and spending in each category and each
+    /// quarter over the given number of steps up to the given date
+    /// (or every step kept if None).
+    pub fn show(&self, date: i32, steps: Option<i32>) -> String {
+        let t = self.totals(date, steps);
+        let row = |name: &str, t: &(f64, f64)| {
+            format!("{:<16}{:>12.2}{:>12.2}\n", name, t.0, t.1)
+        };
+        let mut s = format!("Ledger for steps {} to {}:\n", t.from.max(0), date);
+        s += &format!("{:<16}{:>12}{:>12}\n", "Category", "Income", "Spending");
+        for &(c, ref t) in t.categories.iter() {
+            s += &row(&c.to_string(), t);
+        }
+        s += &row("Total", &t.total);
+        s += &format!("\n{:<16}{:>12}{:>12}\n", "Quarter", "Income", "Spending");
+        for (q, t) in t.quarters.iter() {
+            s += &row(q, t);
+        }
+        s
+    }
+}
+
+/// The (income, spending) totals of a ledger over a window of steps.
+/// Entries without a quarter are totalled under "(sett)".
+#[derive(Debug)]
+pub struct Totals {
+    /// The first step in the window.
+    pub from: i32,
+    pub categories: Vec<(Category, (f64, f64))>,
+    pub quarters: BTreeMap<String, (f64, f64)>,
+    pub total: (f64, f64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger() -> Ledger {
+        let mut l = Ledger::new(10);
+        l.record(1, Category::Tax, None, 10.0);
+        l.record(1, Category::Buildings, Some(String::from("Market")), 4.0);
+        l.record(2, Category::Upkeep, Some(String::from("Market")), -3.0);
+        l.record(3, Category::Tax, None, 12.0);
+        l.record(3, Category::Repairs, Some(String::from("Temple")), -20.0);
+        l
+    }
+
+    fn category(t: &Totals, c: Category) -> (f64, f64) {
+        t.categories.iter().find(|x| x.0 == c).unwrap().1
+    }
+
+    #[test]
+    fn totals_are_kept_by_category_and_quarter() {
+        let t = ledger().totals(3, None);
+        assert_eq!(category(&t, Category::Tax), (22.0, 0.0));
+        assert_eq!(category(&t, Category::Upkeep), (0.0, 3.0));
+        assert_eq!(category(&t, Category::Treasure), (0.0, 0.0));
+        assert_eq!(t.quarters["(sett)"], (22.0, 0.0));
+        assert_eq!(t.quarters["Market"], (4.0, 3.0));
+        assert_eq!(t.quarters["Temple"], (0.0, 20.0));
+        assert_eq!(t.total, (26.0, 23.0));
+    }
+
+    #[test]
+    fn totals_only_count_the_steps_asked_for() {
+        let l = ledger();
+        let t = l.totals(3, Some(2));
+        assert_eq!(t.from, 2);
+        assert_eq!(t.total, (12.0, 23.0));
+        assert_eq!(t.quarters["(sett)"], (12.0, 0.0));
+        // entries after the date are left out too
+        assert_eq!(l.totals(1, Some(1)).total, (14.0, 0.0));
+        assert!(l.show(3, Some(2)).starts_with("Ledger for steps 2 to 3:"));
+    }
+
+    #[test]
+    fn old_and_empty_entries_are_not_kept() {
+        let mut l = Ledger::new(2);
+        l.record(1, Category::Tax, None, 10.0);
+        l.record(1, Category::Events, None, 0.0);
+        assert_eq!(l.entries.len(), 1);
+        l.record(3, Category::Tax, None, 5.0);
+        assert_eq!(l.entries.len(), 1);
+        assert_eq!(l.totals(3, Some(10)).total, (5.0, 0.0));
+    }
+}
@@ -3,7 +3,6 @@ extern crate libc;
 extern crate bincode;
 extern crate serde_json;
 extern crate serde;
-extern crate rouler;
 extern crate rand;
 #[macro_use] extern crate macro_attr;
 #[macro_use] extern crate enum_derive;
@@ -11,6 +10,17 @@ extern crate time;
 extern crate shlex;
 extern crate names;
 
+/// Return true if the expression matches any of the given patterns.
+/// Declared before the modules so that all of them can use it.
+macro_rules! enum_match {
+    ($e:expr, $($p: pat)|+) => {
+        match $e {
+            $($p)|+ => true,
+            _ => false,
+        }
+    };
+}
+
 pub mod libdata;
 mod regions;
 mod sett;
@@ -21,8 +31,11 @@ mod items;
 mod prompts;
 mod events;
 mod effects;
+mod dice;
+mod rng;
 pub mod interpreter;
 mod history;
+mod ledger;
 pub mod manager;
 
 
@@ -38,9 +51,31 @@ help            -   view help file
 license         -   view license file
 a, auto         -   toggle automatic creation and stepping
 new [term]      -   create a new [term]
-rep [term]      -   repair the building term
+rep [term] [n%] -   repair the building term by n% (or restore its ruins)
+rep all [gold]  -   repair the most damaged buildings within a budget
+demolish [term] -   demolish the building term for a fee
+promote [hero]  -   promote a hero to govern the sett
+trigger [event] [quarter] [building]
+                -   fire an event now, at the building if given
+custom [quarter] [building]
+                -   compose an event from effects and fire it now
 step, n, next   -   execute a step
 p, print [term] -   print [term]
+p ledger [n]    -   print the treasury's income and spending over n steps
+p schedule      -   print the events due on coming steps
+p event [name]  -   print an event and the ranges of its dice
 sv, save [file] -   save the settlement to file
 ld, load [file] -   load a settlement from a file
+seed [n]        -   show the random seed, or restart it from n
+dev             -   toggle dev mode, which allows the following commands
+                    and builds without checking the quarter, gold or
+                    prerequisites
+spawn hero [class] [level]
+                -   (dev) create a hero of the class
+spawn item [kind] [value]
+                -   (dev) create an item of the kind in a building
+set gold [n]    -   (dev) set the treasury's gold
+set pop [n]     -   (dev) set the sett's population
+set cond [building] [quarter] [n%|ruined|unbuilt]
+                -   (dev) set the condition of a building
 "#;
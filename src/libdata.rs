@@ -17,10 +17,14 @@ use bincode;
 use regions::Region;
 use buildings::BuildingPlan;
 use events::Event;
-use people::Class;
+use effects::{Area, Effect};
+use people::{Class, Race};
+use quarters::QuarterType;
+use items::ItemKind;
 use manager;
 use prompts::PromptError;
-use rand::{self, Rng};
+use dice::{self, DiceError};
+use rand::Rng;
 
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter};
@@ -40,6 +44,12 @@ pub struct PathList {
     pub evs: PathBuf,
     /// Classes (data)
     pub cls: PathBuf,
+    /// Races (data)
+    pub races: PathBuf,
+    /// Quarter types (data)
+    pub qtypes: PathBuf,
+    /// Item kinds (data)
+    pub kinds: PathBuf,
     /// People (names)
     pub pep: PathBuf,
     /// Items (names)
@@ -50,12 +60,18 @@ pub struct PathList {
 
 impl PathList {
     /// Create a new PathList.
-    fn new<P: AsRef<Path>>(r: P, b: P, e: P, c: P, p: P, i: P, a: P) -> PathList {
+    #[allow(clippy::too_many_arguments)]
+    fn new<P: AsRef<Path>>(r: P, b: P, e: P, c: P, rc: P, qt: P, k: P, p: P, i: P, a: P)
+        -> PathList
+    {
         PathList {
             regs: r.as_ref().to_path_buf(),
             bldgs: b.as_ref().to_path_buf(),
             evs: e.as_ref().to_path_buf(),
             cls: c.as_ref().to_path_buf(),
+            races: rc.as_ref().to_path_buf(),
+            qtypes: qt.as_ref().to_path_buf(),
+            kinds: k.as_ref().to_path_buf(),
             pep: p.as_ref().to_path_buf(),
             its: i.as_ref().to_path_buf(),
             adjs: a.as_ref().to_path_buf(),
@@ -74,16 +90,19 @@ impl PathList {
             return Err(LibError::InvalidPath)
         }
         // Get all json files in each directory
-        let (regs, bldgs, evs, cls) = {
+        let (regs, bldgs, evs, cls, races, qtypes, kinds) = {
             (data.join("regions.json"),
              data.join("buildings.json"),
              data.join("events.json"),
-             data.join("classes.json"))};
+             data.join("classes.json"),
+             data.join("races.json"),
+             data.join("quarters.json"),
+             data.join("items.json"))};
         let (pep, its, adjs) = {
             (names.join("people.txt"),
              names.join("items.txt"),
              names.join("adjectives.txt"))};
-        Ok(PathList::new(regs, bldgs, evs, cls, pep, its, adjs))
+        Ok(PathList::new(regs, bldgs, evs, cls, races, qtypes, kinds, pep, its, adjs))
     }
 }
 
@@ -94,6 +113,9 @@ pub struct DataFiles {
     pub plans: Vec<Rc<BuildingPlan>>,
     pub events: Vec<Rc<Event>>,
     pub classes: Vec<Rc<Class>>,
+    pub races: Vec<Rc<Race>>,
+    pub qtypes: Vec<Rc<QuarterType>>,
+    pub kinds: Vec<Rc<ItemKind>>,
 }
 
 /// A structure for storing name data extracted from files (lib/names/)
@@ -106,53 +128,204 @@ pub struct NameFiles {
 
 impl DataFiles {
     /// Create a new DataFiles struct to track regions, buildings, events,
-    /// and classes.
+    /// classes, races, quarter types and item kinds.
     /// NOTE: Be mindful of the order when providing the parameters!
+    /// Return an error if a file cannot be read, or if the data is invalid
+    /// (see validate and validate_dice).
     pub fn new(region_path: &Path,
                building_path: &Path,
                event_path: &Path,
-               class_path: &Path) -> DataFiles {
-        DataFiles {
-            regions: get_data(region_path).unwrap(),
-            plans: get_data(building_path).unwrap(),
-            events: get_data(event_path).unwrap(),
-            classes: get_data(class_path).unwrap()
+               class_path: &Path,
+               race_path: &Path,
+               qtype_path: &Path,
+               kind_path: &Path) -> Result<DataFiles, LibError> {
+        let df = DataFiles {
+            regions: get_data(region_path)?,
+            plans: get_data(building_path)?,
+            events: get_data(event_path)?,
+            classes: get_data(class_path)?,
+            races: get_data(race_path)?,
+            qtypes: get_data(qtype_path)?,
+            kinds: get_data(kind_path)?,
+        };
+        df.validate()?;
+        df.validate_dice(event_path)?;
+        Ok(df)
+    }
+
+    pub fn from_pathlist(pl: &PathList) -> Result<DataFiles, LibError> {
+        DataFiles::new(&pl.regs, &pl.bldgs, &pl.evs, &pl.cls, &pl.races, &pl.qtypes,
+                       &pl.kinds)
+    }
+
+    /// Check that every building plan and every quarter type's building tags
+    /// name a declared quarter type, that every area filter of an event's
+    /// effects and every building in its conditions names declared
+    /// buildings, quarter types or races, that every follow-up of an event
+    /// names a declared event, that every class names declared races (at
+    /// least one), that every Hero effect names at least one class, and that
+    /// every class and Item effect names declared item kinds (Item effects at
+    /// least one) which can be kept in some declared building.
+    pub fn validate(&self) -> Result<(), LibError> {
+        for c in self.classes.iter() {
+            if c.races.is_empty() {
+                return Err(LibError::NoneListed(format!("Class {}", c.name),
+                                                String::from("races")));
+            }
+            if let Some(r) = c.races.iter().find(|r| self.get_race(r).is_none()) {
+                return Err(LibError::UnknownRace(format!("Class {}", c.name), r.clone()));
+            }
+            if let Some(k) = c.items.iter().find(|k| self.get_item_kind(k).is_none()) {
+                return Err(LibError::UnknownItemKind(format!("Class {}", c.name), k.clone()));
+            }
+        }
+        for e in self.events.iter() {
+            for eff in e.effects.iter() {
+                let source = format!("Event {}", e.name);
+                match *eff {
+                    Effect::Item { ref kind, .. } => {
+                        if kind.is_empty() {
+                            return Err(LibError::NoneListed(source, String::from("item kinds")));
+                        }
+                        if let Some(k) = kind.iter().find(|k| self.get_item_kind(k).is_none()) {
+                            return Err(LibError::UnknownItemKind(source, k.clone()));
+                        }
+                    },
+                    Effect::Hero { ref classes, .. } if classes.is_empty() =>
+                        return Err(LibError::NoneListed(source, String::from("classes"))),
+                    _ => (),
+                }
+            }
+        }
+        for k in self.kinds.iter() {
+            if let Some(b) = k.buildings.iter()
+                .find(|b| *b != "ALL" && !self.declares_building(b))
+            {
+                return Err(LibError::UnknownBuilding(format!("Item kind {}", k.name),
+                                                     b.clone()));
+            }
+        }
+        let check = |source: String, qtype: &String| {
+            if self.qtypes.iter().any(|qt| &qt.name == qtype) {
+                Ok(())
+            } else {
+                Err(LibError::UnknownQType(source, qtype.clone()))
+            }
+        };
+        for qt in self.qtypes.iter() {
+            for t in qt.tags.iter() {
+                check(format!("Quarter type {}", qt.name), t)?;
+            }
+        }
+        for p in self.plans.iter() {
+            check(format!("Building {}", p.name), &p.btype)?;
+        }
+        for e in self.events.iter() {
+            let source = format!("Event {}", e.name);
+            if let Some(b) = e.conditions.buildings.iter().find(|b| !self.declares_building(b)) {
+                return Err(LibError::UnknownBuilding(source, b.clone()));
+            }
+            if let Some(f) = e.follow_ups.iter()
+                .find(|f| !self.events.iter().any(|x| x.name == f.event))
+            {
+                return Err(LibError::UnknownEvent(source, f.event.clone()));
+            }
+            for area in e.effects.iter().filter_map(|eff| eff.area()) {
+                match *area {
+                    Area::Building(ref names) | Area::Quarter(ref names) => {
+                        if let Some(n) = names.iter().find(|n| !self.declares_building(n)) {
+                            return Err(LibError::UnknownBuilding(source, n.clone()));
+                        }
+                    },
+                    Area::Race(ref races) => {
+                        if let Some(r) = races.iter().find(|r| self.get_race(r).is_none()) {
+                            return Err(LibError::UnknownRace(source, r.clone()));
+                        }
+                    },
+                    _ => (),
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Check that every dice expression of every event's effects (read
+    /// from the given file) can be rolled without failing.
+    pub fn validate_dice(&self, event_path: &Path) -> Result<(), LibError> {
+        for e in self.events.iter() {
+            for eff in e.effects.iter() {
+                for (field, expr) in eff.dice() {
+                    dice::stats(expr).map_err(|err| LibError::InvalidDice(
+                        event_path.display().to_string(), e.name.clone(),
+                        format!("{}.{}", eff.kind(), field), err))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the quarter types available to a settlement, leaving out the
+    /// coastal-only types if it is not coastal.
+    pub fn get_qtypes(&self, is_coastal: bool) -> Vec<Rc<QuarterType>> {
+        self.qtypes.iter().filter(|qt| is_coastal || !qt.coastal)
+            .cloned().collect()
+    }
+
+    /// Return true if the given name is that of a declared building plan or
+    /// quarter type (which doubles as a type of building).
+    fn declares_building(&self, name: &str) -> bool {
+        self.plans.iter().any(|p| p.name == name) || self.qtypes.iter().any(|qt| qt.name == name)
+    }
+
+    /// Return the item kind with the given name, if it is declared.
+    pub fn get_item_kind(&self, name: &str) -> Option<Rc<ItemKind>> {
+        self.kinds.iter().find(|k| k.name == name).cloned()
     }
 
-    pub fn from_pathlist(pl: &PathList) -> DataFiles {
-        DataFiles::new(&pl.regs, &pl.bldgs, &pl.evs, &pl.cls)
+    /// Return the race with the given name, if it is declared.
+    pub fn get_race(&self, name: &str) -> Option<Rc<Race>> {
+        self.races.iter().find(|r| r.name == name).cloned()
     }
 }
 
 impl NameFiles {
+    /// Return an error if a file cannot be read.
     pub fn new(people_path: &Path, items_path: &Path, adj_path: &Path)
-        -> NameFiles {
-        NameFiles {
-            people: get_names(people_path).unwrap(),
-            items: get_names(items_path).unwrap(),
-            adjectives: get_names(adj_path).unwrap()
-        }
+        -> Result<NameFiles, LibError> {
+        Ok(NameFiles {
+            people: get_names(people_path)?,
+            items: get_names(items_path)?,
+            adjectives: get_names(adj_path)?
+        })
     }
 
-    pub fn from_pathlist(pl: &PathList) -> NameFiles {
+    pub fn from_pathlist(pl: &PathList) -> Result<NameFiles, LibError> {
         NameFiles::new(&pl.pep, &pl.its, &pl.adjs)
     }
 
     /// Get a random item name using a particular style
-    pub fn get_item(&self) -> String {
-        let desc = rand::thread_rng().choose(&self.adjectives)
+    pub fn get_item<R: Rng>(&self, rng: &mut R) -> String {
+        let desc = rng.choose(&self.adjectives)
             .expect("adjectives namefile has no elements!");
-        let name = rand::thread_rng().choose(&self.people)
+        let name = rng.choose(&self.people)
             .expect("people namefile has no elements!");
         format!("{} {}", desc, name)
     }
 
+    /// Get a random quarter name using a particular style
+    pub fn get_quarter<R: Rng>(&self, rng: &mut R) -> String {
+        let desc = rng.choose(&self.adjectives)
+            .expect("adjectives namefile has no elements!");
+        let name = rng.choose(&self.items)
+            .expect("items namefile has no elements!");
+        format!("{} {}", desc, name)
+    }
+
     /// Get a random hero name using a particular style
-    pub fn get_hero(&self) -> String {
-        let name = rand::thread_rng().choose(&self.people)
+    pub fn get_hero<R: Rng>(&self, rng: &mut R) -> String {
+        let name = rng.choose(&self.people)
             .expect("people namefile has no elements!");
-        let epithet = rand::thread_rng().choose(&self.adjectives)
+        let epithet = rng.choose(&self.adjectives)
             .expect("adjectives namefile has no elements!");
         format!("{} the {}", name, epithet)
     }
@@ -194,6 +367,23 @@ pub enum LibError {
     Io(io::Error),
     Prompt(PromptError),
     InvalidPath,
+    /// The data named by $1 refers to the undeclared quarter type $2.
+    UnknownQType(String, String),
+    /// The data named by $1 refers to the undeclared race $2.
+    UnknownRace(String, String),
+    /// The data named by $1 refers to the undeclared item kind $2.
+    UnknownItemKind(String, String),
+    /// The data named by $1 refers to the undeclared building plan or
+    /// quarter type $2.
+    UnknownBuilding(String, String),
+    /// The data named by $1 refers to the undeclared event $2.
+    UnknownEvent(String, String),
+    /// The data named by $1 lists no $2 (e.g. "races"), where it needs at
+    /// least one.
+    NoneListed(String, String),
+    /// In the file $1, the event $2 has an invalid dice expression in the
+    /// field $3.
+    InvalidDice(String, String, String, DiceError),
 }
 
 impl From<io::Error> for LibError {
@@ -220,6 +410,22 @@ impl fmt::Display for LibError {
             LibError::Io(ref err) => err.fmt(f),
             LibError::Prompt(ref err) => err.fmt(f),
             LibError::InvalidPath => write!(f, "Invalid path given"),
+            LibError::UnknownQType(ref source, ref qtype) =>
+                write!(f, "{} refers to undeclared quarter type \"{}\"", source, qtype),
+            LibError::UnknownRace(ref source, ref race) =>
+                write!(f, "{} refers to undeclared race \"{}\"", source, race),
+            LibError::UnknownItemKind(ref source, ref kind) =>
+                write!(f, "{} refers to undeclared item kind \"{}\"", source, kind),
+            LibError::UnknownBuilding(ref source, ref bldg) =>
+                write!(f, "{} refers to undeclared building or quarter type \"{}\"",
+                       source, bldg),
+            LibError::UnknownEvent(ref source, ref event) =>
+                write!(f, "{} refers to undeclared event \"{}\"", source, event),
+            LibError::NoneListed(ref source, ref what) =>
+                write!(f, "{} lists no {}", source, what),
+            LibError::InvalidDice(ref file, ref event, ref field, ref err) =>
+                write!(f, "{}: Event {} has an invalid dice expression in {} ({})",
+                       file, event, field, err),
         }
     }
 }
@@ -232,6 +438,13 @@ impl error::Error for LibError {
             LibError::Io(ref err) => err.description(),
             LibError::Prompt(ref err) => err.description(),
             LibError::InvalidPath => "invalid path",
+            LibError::UnknownQType(..) => "unknown quarter type",
+            LibError::UnknownRace(..) => "unknown race",
+            LibError::UnknownItemKind(..) => "unknown item kind",
+            LibError::UnknownBuilding(..) => "unknown building",
+            LibError::UnknownEvent(..) => "unknown event",
+            LibError::NoneListed(..) => "nothing listed",
+            LibError::InvalidDice(..) => "invalid dice expression",
         }
     }
 
@@ -241,7 +454,11 @@ impl error::Error for LibError {
             LibError::Bincode(ref err) => err.cause(),
             LibError::Io(ref err) => err.cause(),
             LibError::Prompt(ref err) => err.cause(),
-            LibError::InvalidPath => None,
+            LibError::InvalidDice(_, _, _, ref err) => Some(err),
+            LibError::InvalidPath | LibError::UnknownQType(..)
+                | LibError::UnknownRace(..) | LibError::UnknownItemKind(..)
+                | LibError::UnknownBuilding(..) | LibError::UnknownEvent(..)
+                | LibError::NoneListed(..) => None,
         }
     }
 }
@@ -288,3 +505,67 @@ pub fn load_rbs(fname: &str) -> Result<manager::Manager, LibError> {
     bincode::deserialize_from(&mut reader, bincode::Infinite)
         .map_err(LibError::Bincode)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn bad_dice_in_events_are_rejected() {
+        let evs = std::env::temp_dir().join("podesta_bad_events.json");
+        let mut bad = String::new();
+        fs::File::open("lib/data/events.json")
+            .and_then(|mut f| f.read_to_string(&mut bad)).unwrap();
+        // give the first event's first effect dice with no sides
+        let bad = bad.replacen("5d6 * 2", "5d0 * 2", 1);
+        fs::File::create(&evs).and_then(|mut f| f.write_all(bad.as_bytes())).unwrap();
+        let mut pl = PathList::from_dirs("lib/data/", "lib/names/").unwrap();
+        assert!(DataFiles::from_pathlist(&pl).is_ok());
+        pl.evs = evs.clone();
+        let res = DataFiles::from_pathlist(&pl);
+        fs::remove_file(&evs).unwrap();
+        match res {
+            Err(LibError::InvalidDice(_, ref event, ref field, ref err)) => {
+                assert_eq!(event, "Fire");
+                assert_eq!(field, "Kill.dead");
+                assert_eq!(*err, DiceError::NoSides);
+            },
+            _ => panic!("bad dice were accepted"),
+        }
+    }
+
+    fn datafiles() -> DataFiles {
+        DataFiles::from_pathlist(&PathList::from_dirs("lib/data/", "lib/names/").unwrap())
+            .unwrap()
+    }
+
+    fn none_listed(df: &DataFiles, source: &str, what: &str) -> bool {
+        match df.validate() {
+            Err(LibError::NoneListed(ref s, ref w)) => s == source && w == what,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn classes_without_races_are_rejected() {
+        let mut df = datafiles();
+        let c = Rc::try_unwrap(df.classes.remove(0)).unwrap();
+        let name = c.name.clone();
+        df.classes.push(Rc::new(Class { races: vec![], ..c }));
+        assert!(none_listed(&df, &format!("Class {}", name), "races"));
+    }
+
+    #[test]
+    fn heroes_and_items_must_list_a_choice() {
+        let event = |effect| Rc::new(Event::test("TestGift", vec![effect]));
+        let mut df = datafiles();
+        df.events.push(event(Effect::Hero { level: String::from("1"), classes: vec![] }));
+        assert!(none_listed(&df, "Event TestGift", "classes"));
+        df.events.pop();
+        df.events.push(event(Effect::Item { value: String::from("1"), kind: vec![],
+                                            magical: 0.0 }));
+        assert!(none_listed(&df, "Event TestGift", "item kinds"));
+    }
+}
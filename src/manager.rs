@@ -1,5 +1,5 @@
 //! The manager for a settlement
-//! Tracks metavariables and manages the event queue and its effects on the
+//! Tracks metavariables and manages the event schedule and its effects on the
 //! settlement's members.
 
 use libdata;
@@ -10,15 +10,21 @@ use buildings;
 use items;
 use people;
 use history;
+use ledger;
 use events;
 use effects;
 use prompts;
+use rng;
+use dice;
+use serde_json;
 use std::fmt;
 use std::error;
 use std::rc::Rc;
 use std::cell::RefCell;
-use rand::{self, Rng};
+use std::collections::BTreeSet;
+use rand::Rng;
 use std::result;
+use std::cmp;
 //use std::io::{self, Read, Write};
 
 macro_rules! choose_info {
@@ -37,15 +43,6 @@ macro_rules! print_opt {
     };
 }
 
-macro_rules! enum_match {
-    ($e:expr, $p: pat) => {
-        match $e {
-            $p => true,
-            _ => false,
-        }
-    };
-}
-
 macro_rules! dev_print {
     ($dev:expr, $e:expr) => {
         if $dev {
@@ -62,15 +59,40 @@ macro_rules! match_roll {
 }
 */
 
+// Multiplier on every quarter's growth, build speed and gold for each step
+// that no hero is governing the sett.
+const ANARCHY_PROD: f64 = 0.5;
+
+// Odds (1 in n) that a hero's treasure includes a rare item.
+const TREASURE_ITEM_ODDS: u32 = 4;
+
 type Result<T> = result::Result<T, Error>;
 
+// A building along with the quarter containing it.
+type Sited = (Rc<RefCell<quarters::Quarter>>, Rc<RefCell<buildings::Building>>);
+
+// Quarters struck by an effect, each with their buildings that were struck.
+type Struck = Vec<(Rc<RefCell<quarters::Quarter>>, Vec<Rc<RefCell<buildings::Building>>>)>;
+
 #[derive(Debug)]
 pub enum Error {
     Lib(libdata::LibError),
     Build(quarters::BuildError),
     NoSett,
+    NoHeroFound,
+    HeroBusy,
+    CannotGovern,
     History,
     Event,
+    Effects(Vec<String>),
+    NoEventFound,
+    InvalidEffect,
+    NoClassFound,
+    NoKindFound,
+    CannotHold,
+    InvalidAmount,
+    DevOnly,
+    Dice(dice::DiceError),
 }
 
 impl fmt::Display for Error {
@@ -79,8 +101,21 @@ impl fmt::Display for Error {
             Error::Lib(ref e) => e.fmt(f),
             Error::Build(ref e) => e.fmt(f),
             Error::NoSett => write!(f, "No sett found (first run 'new' or 'load')"),
+            Error::NoHeroFound => write!(f, "No hero of the given name found"),
+            Error::HeroBusy => write!(f, "Hero is not available (must be working)"),
+            Error::CannotGovern => write!(f, "Hero is unable to govern"),
             Error::History => write!(f, "Failed to update history log"),
             Error::Event => write!(f, "Failed to perform event"),
+            Error::Effects(ref kinds) => write!(f, "Failed to perform the effect(s): {}",
+                                                kinds.join(", ")),
+            Error::NoEventFound => write!(f, "No event of the given name found"),
+            Error::InvalidEffect => write!(f, "Invalid value given for the effect"),
+            Error::NoClassFound => write!(f, "No class of the given name found"),
+            Error::NoKindFound => write!(f, "No item kind of the given name found"),
+            Error::CannotHold => write!(f, "No building can hold the item"),
+            Error::InvalidAmount => write!(f, "Invalid amount given"),
+            Error::DevOnly => write!(f, "Only available in dev mode (type 'dev' to toggle)"),
+            Error::Dice(ref e) => write!(f, "Invalid dice expression: {}", e),
         }
     }
 }
@@ -91,14 +126,27 @@ impl error::Error for Error {
             Error::Lib(ref err) => err.description(),
             Error::Build(ref err) => err.description(),
             Error::NoSett => "no sett found",
+            Error::NoHeroFound => "no hero found",
+            Error::HeroBusy => "hero unavailable",
+            Error::CannotGovern => "hero cannot govern",
             Error::History => "unable to write history",
             Error::Event => "unable to perform event",
+            Error::Effects(_) => "unable to perform effects",
+            Error::NoEventFound => "no event found",
+            Error::InvalidEffect => "invalid effect",
+            Error::NoClassFound => "no class found",
+            Error::NoKindFound => "no item kind found",
+            Error::CannotHold => "no building can hold item",
+            Error::InvalidAmount => "invalid amount",
+            Error::DevOnly => "dev mode only",
+            Error::Dice(_) => "invalid dice expression",
         }
     }
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::Lib(ref err) => Some(err),
             Error::Build(ref err) => Some(err),
+            Error::Dice(ref err) => Some(err),
             _ => None,
         }
     }
@@ -122,8 +170,10 @@ pub struct Manager {
     sett: Option<sett::Sett>,
     /// The history tracker for game events
     hist: history::History,
-    /// The queue of events in the settlement
-    queue: events::EventQueue,
+    /// The events due on each coming step of the settlement
+    schedule: events::Schedule,
+    /// The generator used for every random decision in the settlement
+    rng: rng::SimRng,
     /// Whether or not to make random choices automatically
     automate: bool,
     /// Whether or not to print additional information
@@ -143,18 +193,24 @@ pub struct Manager {
 impl Manager {
     /// Create a new Manager with the given data files.
     /// Note that until build_sett() is called, no settlement actually exists.
-    pub fn new(pl: &libdata::PathList, verb: bool) -> Self {
-        Manager {
-            datafiles: libdata::DataFiles::from_pathlist(pl),
-            namefiles: libdata::NameFiles::from_pathlist(pl),
+    /// If no seed is given, one is chosen at random.
+    /// Return an error if the data files cannot be loaded or are invalid.
+    pub fn new(pl: &libdata::PathList, verb: bool, seed: Option<u64>) -> Result<Self> {
+        Ok(Manager {
+            datafiles: libdata::DataFiles::from_pathlist(pl).map_err(Error::Lib)?,
+            namefiles: libdata::NameFiles::from_pathlist(pl).map_err(Error::Lib)?,
             sett: None,
             hist: history::History::new(),
-            queue: events::EventQueue::new(32),
+            schedule: events::Schedule::new(),
+            rng: match seed {
+                Some(n) => rng::SimRng::new(n),
+                None => rng::SimRng::from_entropy(),
+            },
             automate: false,
             verbose: verb,
             dev: false,
             savefile: format!("pod-{}.rbs", time::now().ctime()),
-        }
+        })
     }
 
     /// Load a Manager from a file with the given name; if None is given,
@@ -193,6 +249,18 @@ impl Manager {
         if self.verbose { println!("Dev mode set to {}", self.dev) }
     }
 
+    /// Print the seed of the manager's generator, or restart the generator
+    /// from the given seed.
+    pub fn seed(&mut self, seed: Option<u64>) {
+        match seed {
+            Some(n) => {
+                self.rng.reseed(n);
+                if self.verbose { println!("Seed set to {}", n) }
+            },
+            None => println!("Seed: {}", self.rng.seed()),
+        }
+    }
+
     /// Initialize a new settlement and store it in the manager.
     pub fn build_sett(&mut self, name_input: Option<String>, force: bool) {
         if self.sett.is_none() || force || Self::confirm_overwrite() {
@@ -205,38 +273,96 @@ impl Manager {
             // Region
             choose_info!("{}'s region...", self.automate, name);
             let regchoice = prompts::choose_or_rand(&(self.datafiles.regions),
-                                                    nprompts);
+                                                    nprompts, &mut self.rng);
             let reg = self.datafiles.regions[regchoice].clone();
             // Coastal
             choose_info!("if {} is coastal...", self.automate, name);
             let coastchoice = prompts::bool_choose_or_rand(
                 &format!("Is {} coastal? (y/n): ", name),
-                &["y", "yes"], &["n", "no"], nprompts);
+                &["y", "yes"], &["n", "no"], nprompts, &mut self.rng);
             // Quarter type
             choose_info!("the focus of {}'s main quarter...",
                          self.automate, name);
-            let qchoice = prompts::choose_or_rand(
-                &quarters::QType::get_qtype_names(coastchoice),
-                nprompts);
-            let qtype = quarters::QType::get_qtypes(coastchoice)[qchoice];
+            let qtypes = self.datafiles.get_qtypes(coastchoice);
+            let qchoice = prompts::choose_or_rand(&qtypes, nprompts, &mut self.rng);
+            let qtype = qtypes[qchoice].clone();
             // Race
             choose_info!("the majority race of {}'s main quarter...",
                          self.automate, name);
-            let racenames = people::Race::iter_variant_names();
             let racechoice = prompts::choose_or_rand(
-                &(racenames.collect::<Vec<_>>()), nprompts);
-            let race = people::Race::iter_variants().nth(racechoice).unwrap();
+                &self.datafiles.races, nprompts, &mut self.rng);
+            let race = self.datafiles.races[racechoice].clone();
+            // Governor
+            let gov = self.starting_governor(race.clone());
+            if let Some(ref g) = gov {
+                self.hist.add_entry(0, format!("{} was appointed the first governor of {}.",
+                                               g.name, name));
+            }
 
             self.savefile = format!("{}.rbs", &name);
 
             self.sett = Some(
-                sett::Sett::new(name, reg, qtype, race, coastchoice)
+                sett::Sett::new(name, reg, qtype, race, coastchoice, gov)
             );
 
             if self.verbose { println!("{}", self.sett.as_ref().unwrap()) }
         }
     }
 
+    /// Create a level 1 hero of the given race to serve as a new sett's first
+    /// governor, choosing a random class open to that race.
+    fn starting_governor(&mut self, race: Rc<people::Race>) -> Option<people::Hero> {
+        let classes = self.datafiles.classes.iter()
+            .filter(|c| c.races.contains(&race.name))
+            .cloned().collect::<Vec<_>>();
+        let class = self.rng.choose(&classes).cloned();
+        class.map(|c| {
+            let name = self.namefiles.get_hero(&mut self.rng);
+            people::Hero::new(&name, 1, race, c)
+        })
+    }
+
+    /// Promote a hero to govern the sett, replacing any current governors.
+    /// If no hero name is given, prompt the user to choose one.
+    pub fn promote(&mut self, hero_input: Option<String>) {
+        match self.sett {
+            Some(ref s) => {
+                let qbheroes = match hero_input {
+                    Some(ref h) => s.find_heroes(h),
+                    None => s.get_heroes(),
+                };
+                let names : Vec<_> = qbheroes.iter()
+                    .map(|(q, b, h)| {
+                        format!("{} (in {} of {})", h.borrow().name, b, q)
+                    }).collect();
+                let hero = prompts::prechoose(&names, None)
+                    .map(|i| qbheroes[i].2.clone())
+                    .map_err(|_| Error::NoHeroFound);
+                let hist = &mut self.hist;
+                let verbose = self.verbose;
+                hero.and_then(|h| {
+                    if h.borrow().activity != people::Activity::Working {
+                        return Err(Error::HeroBusy);
+                    }
+                    if h.borrow().act_boost("Governing") <= 0.0 {
+                        return Err(Error::CannotGovern);
+                    }
+                    for g in s.get_governors() {
+                        g.borrow_mut().activity = people::Activity::Working;
+                        hist.add_entry(s.age, format!("Governor {} was replaced by {}.",
+                                                      g.borrow().name, h.borrow().name));
+                    }
+                    h.borrow_mut().make_governor();
+                    hist.add_entry(s.age, format!("{} was promoted to govern {}.",
+                                                  h.borrow().name, s.name));
+                    if verbose { println!("{} now governs {}.", h.borrow().name, s.name) }
+                    Ok(())
+                })
+            },
+            None => Err(Error::NoSett),
+        }.unwrap_or_else(|e| println!("Failed to promote hero: {}", e))
+    }
+
     /// Return the name of the savefile.
     pub fn get_savefile(&self) -> String {
         self.savefile.clone()
@@ -255,30 +381,63 @@ impl Manager {
                 // Quarter type
                 choose_info!("the focus of the {} quarter...",
                              self.automate, name);
-                let qchoice = prompts::choose_or_rand(
-                    &quarters::QType::get_qtype_names(s.coastal),
-                    nprompts);
-                let qtype = quarters::QType::get_qtypes(s.coastal)[qchoice];
+                let qtypes = self.datafiles.get_qtypes(s.coastal);
+                let qchoice = prompts::choose_or_rand(&qtypes, nprompts, &mut self.rng);
+                let qtype = qtypes[qchoice].clone();
                 // Race
                 choose_info!("the majority race of the {} quarter...",
                              self.automate, name);
-                let racenames = people::Race::iter_variant_names();
                 let racechoice = prompts::choose_or_rand(
-                    &(racenames.collect::<Vec<_>>()), nprompts);
-                let race = people::Race::iter_variants()
-                    .nth(racechoice).unwrap();
+                    &self.datafiles.races, nprompts, &mut self.rng);
+                let race = self.datafiles.races[racechoice].clone();
+                let hist = &mut self.hist;
+                let focus = qtype.name.clone();
+                let racename = race.name.clone();
                 s.add_quarter(name.clone(), qtype, race).map_err(Error::Build)
+                    .map(|_| hist.add_entry(s.age, format!(
+                        "The {} Quarter was founded, with a {} focus and a mostly {} population.",
+                        name, focus, racename)))
             },
             None => Err(Error::NoSett),
         }.unwrap_or_else(|e| println!("Failed to construct quarter: {}", e))
     }
 
+    /// Found a new quarter once the sett has grown enough.
+    /// In auto mode, the quarter's name is chosen automatically; otherwise
+    /// the user is prompted for it.
+    fn found_quarter(&mut self) {
+        let name = match self.sett {
+            Some(ref s) => {
+                println!("{} has grown enough to found a new quarter! (step {})",
+                         s.name, s.age);
+                if self.automate {
+                    let base = self.namefiles.get_quarter(&mut self.rng);
+                    let mut name = base.clone();
+                    let mut i = 1;
+                    while s.find_quarter(&name).is_some() {
+                        i += 1;
+                        name = format!("{} {}", base, i);
+                    }
+                    Some(name)
+                } else {
+                    None
+                }
+            },
+            None => return,
+        };
+        self.build_quarter(name);
+    }
+
     /// Initialize a new building and store it in the manager's sett's quarter.
+    /// In dev mode, the building may go in any quarter and is free of cost
+    /// and prerequisites.
     #[allow(unused_variables)]
     pub fn build_building(&mut self,
                           name_input: Option<String>,
                           quarter_input: Option<String>)
     {
+        let dev = self.dev;
+        let hist = &mut self.hist;
         match self.sett {
             Some(ref mut s) => {
                 let ref plans = self.datafiles.plans;
@@ -300,7 +459,7 @@ impl Manager {
                     let qrtr = {
                         // Borrow s and plan within block to avoid borrow errors
                         let valid_qrtrs = s.qrtrs.iter().filter(|ref q| {
-                            p.btype == q.borrow().qtype
+                            dev || q.borrow().qtype.allows(&p)
                         });
                         let valqrtrs : Vec<_> = valid_qrtrs.collect();
                         let qnames = valqrtrs.iter()
@@ -314,325 +473,1060 @@ impl Manager {
                             .map(|i| valqrtrs[i].clone())
                             .map_err(Error::Build)
                     };
-                    qrtr.and_then(|q| s.add_building(p, q).map_err(Error::Build))
+                    qrtr.and_then(|q| if dev {
+                        q.borrow_mut().add_building(p.clone()).map_err(Error::Build)?;
+                        hist.add_authored(s.age, format!(
+                            "Construction of the {} began in the {} Quarter.",
+                            p.name, q.borrow().name), history::Author::Dev);
+                        Ok(())
+                    } else {
+                        s.add_building(p, q).map_err(Error::Build)
+                    })
                 })
             },
             None => Err(Error::NoSett),
         }.unwrap_or_else(|e| println!("Failed to construct building: {}", e))
     }
 
-    /// Pay n gold to repair a building.
-    pub fn repair_building(&mut self, name_input: Option<String>, quarter_input: Option<String>) {
+    /// Dev mode only. Create a new hero of the given class and level, or a
+    /// new item of the given kind and value (see spawn_hero and spawn_item).
+    pub fn spawn(&mut self, target: Option<String>, name: Option<String>,
+                 amount: Option<String>)
+    {
+        let amount = amount.and_then(|n| n.parse::<i32>().ok());
+        match target.as_deref() {
+            Some("hero") => self.as_dev("spawn hero", |m| m.spawn_hero(name, amount)),
+            Some("item") => self.as_dev("spawn item", |m| m.spawn_item(name, amount)),
+            _ => println!("Invalid target for 'spawn' (specify 'hero' or 'item')!"),
+        }
+    }
+
+    /// Dev mode only. Set the sett's gold or population to the given
+    /// amount, or the condition of the given building (see set_cond).
+    pub fn set(&mut self, target: Option<String>, term1: Option<String>,
+               term2: Option<String>, term3: Option<String>)
+    {
+        let amount = term1.as_ref().and_then(|n| n.parse::<f64>().ok());
+        match target.as_deref() {
+            Some("gold") => self.as_dev("set gold", |m| {
+                let s = m.sett.as_mut().ok_or(Error::NoSett)?;
+                let gold = amount.ok_or(Error::InvalidAmount)?;
+                // keep the ledger balanced with the treasury
+                let change = gold - s.gold;
+                s.transact(ledger::Category::Adjustments, None, change);
+                m.hist.add_entry(s.age, format!("The treasury was set to {:.0} gold.", s.gold));
+                Ok(())
+            }),
+            Some("pop") => self.as_dev("set population", |m| {
+                let s = m.sett.as_mut().ok_or(Error::NoSett)?;
+                let pop = amount.filter(|&n| n >= 0.0).ok_or(Error::InvalidAmount)?;
+                // scale every quarter to keep their shares of the population
+                let total = s.qrtrs.iter().map(|q| q.borrow().pop).sum::<f64>();
+                for (i, q) in s.qrtrs.iter().enumerate() {
+                    let mut q = q.borrow_mut();
+                    q.pop = if total > 0.0 {
+                        q.pop * pop / total
+                    } else if i == 0 {
+                        pop
+                    } else {
+                        0.0
+                    };
+                }
+                s.pop = pop;
+                m.hist.add_entry(s.age, format!("The population was set to {:.0}.", pop));
+                Ok(())
+            }),
+            Some("cond") => self.as_dev("set condition", |m| m.set_cond(term1, term2, term3)),
+            _ => println!("Invalid target for 'set' (specify 'gold', 'pop' or 'cond')!"),
+        }
+    }
+
+    /// Run the named dev mode command, marking every entry it adds to the
+    /// history (see history::Author::Dev). Outside of dev mode, do nothing.
+    fn as_dev<F>(&mut self, cmd: &str, f: F)
+        where F: FnOnce(&mut Manager) -> Result<()>
+    {
+        if !self.dev {
+            println!("Failed to {}: {}", cmd, Error::DevOnly);
+            return;
+        }
+        let n = self.hist.entries.len();
+        f(self).unwrap_or_else(|e| println!("Failed to {}: {}", cmd, e));
+        self.hist.mark_since(n, history::Author::Dev);
+    }
+
+    /// Create a hero of the given class (or a random one) and level (or 1),
+    /// and house them as though they had just arrived.
+    fn spawn_hero(&mut self, class: Option<String>, level: Option<i32>) -> Result<()> {
+        let class = match class {
+            Some(c) => c,
+            None => self.rng.choose(&self.datafiles.classes).map(|c| c.name.clone())
+                .ok_or(Error::NoClassFound)?,
+        };
+        let h = self.create_hero(level.unwrap_or(1).max(1), &class).ok_or(Error::NoClassFound)?;
+        let age = self.sett.as_ref().ok_or(Error::NoSett)?.age;
+        let info = {
+            let h = h.borrow();
+            format!("{}, a level {} {} {}, was spawned.", h.name, h.level, h.race, h.class)
+        };
+        if self.verbose { println!("{}", info) }
+        self.hist.add_entry(age, info);
+        self.house_hero(h);
+        Ok(())
+    }
+
+    /// Create an item of the given kind (or a random one) worth the given
+    /// value (or 100 gold), and keep it in a random building that can hold
+    /// it (see Building::can_hold). Return an error if there is none.
+    fn spawn_item(&mut self, kind: Option<String>, value: Option<i32>) -> Result<()> {
+        let kind = match kind {
+            Some(k) => self.datafiles.get_item_kind(&k),
+            None => self.rng.choose(&self.datafiles.kinds).cloned(),
+        }.ok_or(Error::NoKindFound)?;
+        let age = self.sett.as_ref().ok_or(Error::NoSett)?.age;
+        let name = self.namefiles.get_item(&mut self.rng);
+        let power = kind.roll_power(0.0, &mut self.rng);
+        let item = items::Item::new(&name, kind, power, value.unwrap_or(100).max(0) as f64);
+        let b = self.rand_building_for(&item).ok_or(Error::CannotHold)?;
+        let info = format!("{} was spawned in the {}.", item, b.borrow().name);
+        b.borrow_mut().add_item(Rc::new(RefCell::new(item))).map_err(|_| Error::CannotHold)?;
+        if self.verbose { println!("{}", info) }
+        self.hist.add_entry(age, info);
+        Ok(())
+    }
+
+    /// Force the given building into a condition: a durability in %,
+    /// "ruined", or "unbuilt" (just begun). Heroes driven out of a ruined
+    /// building stay in town until they find a new headquarters.
+    fn set_cond(&mut self, name_input: Option<String>, quarter_input: Option<String>,
+                cond: Option<String>) -> Result<()>
+    {
+        let cond = match cond.as_deref() {
+            Some("ruined") => Some(buildings::BldgCond::Ruined),
+            Some("unbuilt") => Some(buildings::BldgCond::InProgress(0.0)),
+            Some(c) => c.trim_end_matches('%').parse::<f64>().ok()
+                .filter(|&n| n > 0.0)
+                .map(|n| buildings::BldgCond::InUse(n.min(100.0))),
+            None => None,
+        }.ok_or(Error::InvalidAmount)?;
+        let s = self.sett.as_mut().ok_or(Error::NoSett)?;
+        let (q, b) = Manager::choose_building(s, name_input, quarter_input)?;
+        let evicted = b.borrow_mut().set_cond(cond);
+        let info = {
+            let b = b.borrow();
+            let place = format!("The {} in the {} Quarter", b.name, q.borrow().name);
+            match b.cond {
+                buildings::BldgCond::InUse(_) => format!("{} was set to {}.", place, b.cond),
+                buildings::BldgCond::InProgress(_) =>
+                    format!("{} was set back to the start of construction.", place),
+                buildings::BldgCond::Ruined => format!("{} was ruined.", place),
+            }
+        };
+        s.visitors.extend(evicted);
+        if self.verbose { println!("{}", info) }
+        self.hist.add_entry(s.age, info);
+        Ok(())
+    }
+
+    /// Find the building named by the given building and quarter names,
+    /// prompting for the quarter and the building if they are not given.
+    fn choose_building(s: &sett::Sett, name_input: Option<String>, quarter_input: Option<String>)
+        -> Result<Sited>
+    {
+        // get quarter
+        // TODO: allow to skip quarter match if only one possible quarter exists for
+        // building?
+        let qrtr = match quarter_input.and_then(|q| s.find_quarter(&q)) {
+            Some(q) => Ok(q),
+            None => {
+                // prompt for a quarter
+                let qrtrnames = s.qrtrs.iter()
+                    .map(|q| q.borrow().name.clone());
+                let qnamesv = qrtrnames.collect::<Vec<_>>();
+                prompts::choose(&qnamesv)
+                    .map(|i| s.qrtrs[i].clone())
+                    .map_err(|_| quarters::BuildError::NoQuarterFound)
+                    .map_err(Error::Build)
+            },
+        };
+        // get building
+        qrtr.and_then(|q| {
+            let bldg = match name_input {
+                Some(ref name) => {
+                    q.borrow().find_building(name)
+                        .ok_or(quarters::BuildError::NoBuildingFound)
+                },
+                None => {
+                    // prompt for a building
+                    let bldgs = &q.borrow().bldgs;
+                    let bnames = bldgs.iter()
+                        .map(|b| b.borrow().name.clone());
+                    let bnamesv = bnames.collect::<Vec<_>>();
+                    prompts::choose(&bnamesv)
+                        .map(|i| bldgs[i].clone())
+                        .map_err(|_| quarters::BuildError::NoBuildingFound)
+                },
+            }.map_err(Error::Build);
+            bldg.map(|b| (q, b))
+        })
+    }
+
+    /// Pay gold to repair a building immediately, or to restore it if it is
+    /// ruined. If points is given, only repair that many points of the
+    /// building's condition (e.g. 25 to take it from 50% to 75%).
+    /// Nothing is paid for a building already in full repair.
+    pub fn repair_building(&mut self, name_input: Option<String>, quarter_input: Option<String>,
+                           points: Option<f64>)
+    {
         match self.sett {
             Some(ref mut s) => {
-                //TODO: rewrite repair_building to use find_buildings
-                /*
-                match name_input {
-                    Some(b) => {
-                        // get all buildings based on the input name
-                        let qbldgs = s.find_buildings(b);
-                    },
-                    None => {
-                        // get all buildings
-                        let qbldgs = s.get_buildings();
-                    },
-                }
-                */
-                // get quarter
-                // TODO: allow to skip quarter match if only one possible quarter exists for
-                // building?
-                let qrtr = match quarter_input.and_then(|q| s.find_quarter(&q)) {
-                    Some(q) => Ok(q),
-                    None => {
-                        // prompt for a quarter
-                        let qrtrnames = s.qrtrs.iter()
-                            .map(|ref q| q.borrow().name.clone());
-                        let qnamesv = qrtrnames.collect::<Vec<_>>();
-                        prompts::choose(&qnamesv)
-                            .map(|i| s.qrtrs[i].clone())
-                            .map_err(|_| quarters::BuildError::NoQuarterFound)
-                            .map_err(Error::Build)
-                    },
-                };
-                // get building
-                let bldg = qrtr.and_then(|q| {
-                    match name_input {
-                        Some(ref name) => {
-                            // check if building exists
-                            // get quarter
-                            // get building
-                            s.find_building(name, &q.borrow().name.clone())
-                                .ok_or(quarters::BuildError::NoBuildingFound)
-                        },
-                        None => {
-                            // prompt for a building
-                            let ref bldgs = q.borrow().bldgs;
-                            let bnames = bldgs.iter()
-                                .map(|ref b| b.borrow().name.clone());
-                            let bnamesv = bnames.collect::<Vec<_>>();
-                            prompts::choose(&bnamesv)
-                                .map(|i| bldgs[i].clone())
-                                .map_err(|_| quarters::BuildError::NoQuarterFound)
-                        },
-                    }.map_err(Error::Build)
-                });
+                let bldg = Manager::choose_building(s, name_input, quarter_input);
+                let hist = &mut self.hist;
+                let verbose = self.verbose;
                 // check that we have enough gold to purchase repairs
-                bldg.and_then(|b| {
-                    let cost = b.borrow().get_rep_cost();
-                    if let Ok(c) = cost {
-                        if s.gold >= c {
-                            s.gold -= c;
-                            //FIXME: queues up repair for next step but caps at 100, allowing
-                            //wasted value
-                            b.borrow_mut().boosts.build_bonus += effects::EffectStep::new(100f64, 1);
-                            Ok(())
-                        } else {
-                            Err(Error::Build(quarters::BuildError::NotEnoughGold))
-                        }
-                    } else {
-                        //TODO: unhelpful as building does exist but is ruined
-                        Err(Error::Build(quarters::BuildError::NoBuildingFound))
+                bldg.and_then(|(q, b)| {
+                    let cost = b.borrow().get_rep_cost(points)
+                        .map_err(|e| Error::Build(e.into()))?;
+                    if cost <= 0.0 {
+                        return Err(Error::Build(quarters::BuildError::NothingToRepair));
                     }
+                    if s.gold < cost {
+                        return Err(Error::Build(quarters::BuildError::NotEnoughGold));
+                    }
+                    let place = format!("the {} in the {} Quarter",
+                                        b.borrow().name, q.borrow().name);
+                    let ruined = b.borrow().cond == buildings::BldgCond::Ruined;
+                    let info = if ruined {
+                        b.borrow_mut().restore().map_err(|e| Error::Build(e.into()))?;
+                        format!("Restoration of the ruins of {} began, at a cost of {:.0} gold.",
+                                place, cost)
+                    } else {
+                        b.borrow_mut().repair(points.unwrap_or(100.0))
+                            .map_err(|e| Error::Build(e.into()))?;
+                        format!("Repairs to {} left it at {}, at a cost of {:.0} gold.",
+                                place, b.borrow().cond, cost)
+                    };
+                    // only pay once the work has begun
+                    s.transact(ledger::Category::Repairs, Some(q.borrow().name.clone()), -cost);
+                    if verbose { println!("{}", info) }
+                    hist.add_entry(s.age, info);
+                    Ok(())
                 })
             },
             None => Err(Error::NoSett),
         }.unwrap_or_else(|e| println!("Failed to repair building: {}", e))
     }
 
-    /// Execute n settlement steps and perform all events sequentially.
-    /// Write any relevant occurrences to the history.
-    pub fn step(&mut self, n: i64) {
-        let queue_up = match self.sett {
+    /// Repair the buildings in use, most damaged first, until the given
+    /// budget (or all of the sett's gold if None) has been spent.
+    /// Ruined buildings are not restored.
+    pub fn repair_all(&mut self, budget: Option<f64>) {
+        match self.sett {
             Some(ref mut s) => {
-                for _ in 0..n {
-                    self.hist.add_entry(s.age, format!("{}", s));
-                    let emap = s.step();
-                    for event in emap.rand_events().iter() {
-                        let ev = self.datafiles.events.iter()
-                            .find(|e| e.name == *event).map(|e| e.clone());
-                        if let Some(e) = ev {
-                            println!("{} (step {})", e.desc.replace("{}", &s.name), s.age);
-                            self.hist.add_entry(s.age, e.desc.replace("{}", &s.name));
-                            self.queue.push(e);
-                        }
+                let mut budget = budget.unwrap_or(s.gold).min(s.gold).max(0.0);
+                let mut damaged = s.get_buildings().into_iter()
+                    .filter_map(|(q, b)| match b.borrow().cond {
+                        buildings::BldgCond::InUse(c) if c < 100.0 => Some((c, q, b.clone())),
+                        _ => None,
+                    }).collect::<Vec<_>>();
+                damaged.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(cmp::Ordering::Equal));
+                let mut spent = 0.0;
+                let mut repaired = 0;
+                for (_, q, b) in damaged {
+                    let rate = b.borrow().get_rep_rate();
+                    let points = if rate > 0.0 { budget / rate } else { 100.0 };
+                    let points = match b.borrow_mut().repair(points) {
+                        Ok(p) if p > 0.0 => p,
+                        _ => continue,
+                    };
+                    let cost = rate * points;
+                    budget -= cost;
+                    spent += cost;
+                    repaired += 1;
+                    s.transact(ledger::Category::Repairs, Some(q.clone()), -cost);
+                    self.hist.add_entry(s.age, format!(
+                        "Repairs to the {} in the {} Quarter left it at {}, at a cost of {:.0} gold.",
+                        b.borrow().name, q, b.borrow().cond, cost));
+                    if budget <= 0.0 {
+                        break;
                     }
                 }
-                Ok(())
+                println!("Spent {:.0} gold on repairs to {} building(s).", spent, repaired);
+            },
+            None => println!("Failed to repair buildings: {}", Error::NoSett),
+        }
+    }
+
+    /// Pay a fee to demolish a building, clearing it from its quarter.
+    /// Its occupants stay in town until they find a new headquarters, and
+    /// its items are lost.
+    pub fn demolish_building(&mut self, name_input: Option<String>,
+                             quarter_input: Option<String>)
+    {
+        match self.sett {
+            Some(ref mut s) => {
+                let bldg = Manager::choose_building(s, name_input, quarter_input);
+                let automate = self.automate;
+                let hist = &mut self.hist;
+                let verbose = self.verbose;
+                bldg.and_then(|(q, b)| {
+                    let (name, ruined, cost) = {
+                        let b = b.borrow();
+                        (b.name.clone(), b.cond == buildings::BldgCond::Ruined,
+                         b.get_demolish_cost())
+                    };
+                    if !(ruined || automate || prompts::bool_choose(
+                            &format!("The {} is not in ruins. Demolish it anyway? (y/n): ", name),
+                            &["y", "yes"], &["n", "no"]).unwrap_or(false)) {
+                        return Ok(());
+                    }
+                    if s.gold < cost {
+                        return Err(Error::Build(quarters::BuildError::NotEnoughGold));
+                    }
+                    s.transact(ledger::Category::Construction, Some(q.borrow().name.clone()),
+                               -cost);
+                    q.borrow_mut().remove_building(&name);
+                    s.visitors.append(&mut b.borrow_mut().occupants);
+                    let info = format!("The {} in the {} Quarter was demolished, at a cost \
+                                        of {:.0} gold.", name, q.borrow().name, cost);
+                    if verbose { println!("{}", info) }
+                    hist.add_entry(s.age, info);
+                    Ok(())
+                })
             },
             None => Err(Error::NoSett),
+        }.unwrap_or_else(|e| println!("Failed to demolish building: {}", e))
+    }
+
+    /// Fire the named event from the data files now, as caused by the GM,
+    /// prompting for the event if none is given. If a quarter or building
+    /// is given, the event happens at that building (see choose_building).
+    pub fn trigger_event(&mut self, event_input: Option<String>, quarter_input: Option<String>,
+                         name_input: Option<String>)
+    {
+        let event = match event_input {
+            Some(name) => self.datafiles.events.iter().find(|e| e.name == name).cloned()
+                .ok_or(Error::NoEventFound),
+            None => {
+                let names = self.datafiles.events.iter().map(|e| e.name.clone())
+                    .collect::<Vec<_>>();
+                prompts::choose(&names).map(|i| self.datafiles.events[i].clone())
+                    .map_err(|e| Error::Lib(libdata::LibError::Prompt(e)))
+            },
         };
-        queue_up.and_then(|_| {
-            // Pop events until queue is empty.
-            let activated = {
-                let mut a = Ok(vec!());
-                while self.queue.len() > 0 {
-                    a = self.activate_event()
+        event.and_then(|e| {
+            let origin = self.choose_origin(name_input, quarter_input)?;
+            self.perform_now(e, origin, history::Author::GM)
+        }).unwrap_or_else(|e| println!("Failed to trigger event: {}", e))
+    }
+
+    /// Compose an event from effects given by the GM and fire it now. If a
+    /// quarter or building is given, the event happens at that building
+    /// (see choose_building). Prompt for the event's name, description and
+    /// effects, and for each effect's fields (see effects::Effect::fields).
+    pub fn custom_event(&mut self, quarter_input: Option<String>, name_input: Option<String>) {
+        let prompt = |q: &str| prompts::text(q).map_err(|e| Error::Lib(libdata::LibError::Prompt(e)));
+        let composed = self.choose_origin(name_input, quarter_input).and_then(|origin| {
+            let name = prompt("Name the event (e.g. Dragon): ")?;
+            let desc = prompt("Describe the event, with {} for where it happened: ")?;
+            let mut kinds = effects::EFFECT_KINDS.to_vec();
+            kinds.push("Done");
+            let mut effs = vec![];
+            println!("Choose the event's effects:");
+            while let Ok(i) = prompts::choose(&kinds) {
+                if kinds[i] == "Done" {
+                    break;
+                }
+                match Manager::compose_effect(kinds[i]) {
+                    Ok(eff) => effs.push(eff),
+                    Err(e) => println!("{}; the effect was left out.", e),
                 }
-                a
+            }
+            let name = if name.is_empty() { "Custom".to_string() } else { name };
+            let event = events::Event {
+                desc: if desc.is_empty() { format!("{} struck {{}}", name) } else { desc },
+                name,
+                id: 0,
+                chance: 0,
+                effects: effs,
+                conditions: events::Conditions::default(),
+                cooldown: 0,
+                exclusive: vec![],
+                follow_ups: vec![],
             };
-            activated.map(|_| ())
-        }).unwrap_or_else(|e| println!("Failed to perform step: {}", e))
-    }
-
-    /// Pop an event and perform its effects on the sett.
-    /// If successful, return a Result<Vec<()>> with len == events executed.
-    /// If a failure occurs, return Error::Event.
-    pub fn activate_event(&mut self) -> Result<Vec<()>> {
-        if let Some(e) = self.queue.pop() {
-            use effects::RolledEffect as Rolled;
-            let rolled = e.activate();
-            rolled.iter().map(|r| {
-                match *r {
-                    Rolled::Kill(ref step, ref area) => {
-                        //TODO: does MulAssign for a bonus boost always leave it at zero?
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).map(|b| {
-                                    b.borrow_mut().boosts.grow_bonus *= step.clone(); })
-                            },
-                            effects::Area::Quarter(ref qts) => {
-                                self.rand_quarter(&qts).map(|q| {
-                                    q.borrow_mut().boosts.grow_bonus *= step.clone(); })
-                            },
-                            effects::Area::Sett => {
-                                self.sett.as_ref().map(|s| {
-                                    for q in s.qrtrs.iter() {
-                                        q.borrow_mut().boosts.grow_bonus *= step.clone();
-                                    }
-                                })
-                            },
+            Ok((Rc::new(event), origin))
+        });
+        composed.and_then(|(e, origin)| self.perform_now(e, origin, history::Author::GM))
+            .unwrap_or_else(|e| println!("Failed to perform custom event: {}", e))
+    }
+
+    /// Prompt for each field of the named kind of effect (see
+    /// effects::Effect::fields) and return the effect.
+    fn compose_effect(kind: &str) -> Result<effects::Effect> {
+        let mut fields = vec![];
+        for &(field, ty) in effects::Effect::fields(kind) {
+            let input = prompts::text(&format!("{} ({}): ", field, ty))
+                .map_err(|e| Error::Lib(libdata::LibError::Prompt(e)))?;
+            if ty == effects::Field::Dice {
+                // say why an expression that could not be rolled is rejected
+                dice::stats(input.trim()).map_err(Error::Dice)?;
+            }
+            let json = ty.to_json(&input).ok_or(Error::InvalidEffect)?;
+            fields.push(format!("\"{}\": {}", field, json));
+        }
+        let json = format!("{{\"{}\": {{{}}}}}", kind, fields.join(", "));
+        serde_json::from_str(&json).map_err(|_| Error::InvalidEffect)
+    }
+
+    /// Return where an event happens, if a building or quarter is given
+    /// (see choose_building), or None otherwise.
+    fn choose_origin(&self, name_input: Option<String>, quarter_input: Option<String>)
+        -> Result<Option<events::Origin>>
+    {
+        let s = self.sett.as_ref().ok_or(Error::NoSett)?;
+        if name_input.is_none() && quarter_input.is_none() {
+            return Ok(None);
+        }
+        Manager::choose_building(s, name_input, quarter_input).map(|(q, b)| Some(events::Origin {
+            quarter: q.borrow().name.clone(),
+            building: b.borrow().name.clone(),
+        }))
+    }
+
+    /// Fire the given event now, regardless of its conditions, recording it
+    /// in the history by the given author.
+    fn perform_now(&mut self, e: Rc<events::Event>, origin: Option<events::Origin>,
+                   author: history::Author) -> Result<()>
+    {
+        {
+            let s = self.sett.as_mut().ok_or(Error::NoSett)?;
+            s.fired.insert(e.name.clone(), s.age);
+            let desc = e.describe(origin.as_ref(), &s.name);
+            println!("{} (step {})", desc, s.age);
+            self.hist.add_authored(s.age, desc, author);
+        }
+        self.activate_event(events::Scheduled { event: e, origin, after: None }).map(|_| ())
+    }
+
+    /// Execute n settlement steps and perform all events sequentially.
+    /// Write any relevant occurrences to the history.
+    pub fn step(&mut self, n: i64) {
+        let stepped = if self.sett.is_some() {
+            for _ in 0..n {
+                let founding = match self.sett {
+                    Some(ref mut s) => {
+                        // without a governor, the sett suffers from anarchy
+                        // (unless a successor can be promoted in auto mode)
+                        let mut govs = s.get_governors();
+                        if govs.is_empty() && self.automate
+                            && Manager::promote_successor(s, &mut self.hist, &mut self.rng)
+                        {
+                            govs = s.get_governors();
                         }
-                    },
-                    Rolled::Damage(ref step, ref area) => {
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).map(|b| {
-                                    b.borrow_mut().boosts.build_bonus *= step.clone(); })
-                            },
-                            effects::Area::Quarter(ref qts) => {
-                                self.rand_quarter(&qts).map(|q| {
-                                    q.borrow_mut().boosts.build_bonus *= step.clone(); })
-                            },
-                            effects::Area::Sett => {
-                                self.sett.as_ref().map(|s| {
-                                    for q in s.qrtrs.iter() {
-                                        q.borrow_mut().boosts.build_bonus *= step.clone();
-                                    }
-                                })
-                            },
+                        if govs.is_empty() {
+                            for t in &[effects::Target::Grow, effects::Target::Build,
+                                       effects::Target::Gold] {
+                                s.boosts.add(effects::Modifier::new(
+                                    "Anarchy", "no governor", *t, effects::Combine::Mul,
+                                    ANARCHY_PROD, 1));
+                            }
                         }
-                    },
-                    Rolled::Riot(ref step, ref area) => {
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).map(|b| {
-                                    b.borrow_mut().boosts.grow *= step.clone();
-                                    b.borrow_mut().boosts.build *= step.clone();
-                                    b.borrow_mut().boosts.gold *= step.clone(); })
-                            },
-                            effects::Area::Quarter(ref qts) => {
-                                self.rand_quarter(&qts).map(|q| {
-                                    q.borrow_mut().boosts.grow *= step.clone();
-                                    q.borrow_mut().boosts.build *= step.clone();
-                                    q.borrow_mut().boosts.gold *= step.clone(); })
-                            },
-                            effects::Area::Sett => {
-                                self.sett.as_ref().map(|s| {
-                                    for q in s.qrtrs.iter() {
-                                        q.borrow_mut().boosts.grow *= step.clone();
-                                        q.borrow_mut().boosts.build *= step.clone();
-                                        q.borrow_mut().boosts.gold *= step.clone();
-                                    }
-                                })
-                            },
+                        self.hist.add_entry(s.age, format!("{}", s));
+                        let mut emap = s.step(&self.datafiles.qtypes, &mut self.rng);
+                        for (origin, evicted) in emap.ruined.drain(..) {
+                            let mut info = format!("The {} in the {} Quarter fell into ruin.",
+                                                   origin.building, origin.quarter);
+                            if !evicted.is_empty() {
+                                let names = evicted.iter().map(|h| h.borrow().name.clone())
+                                    .collect::<Vec<_>>();
+                                info = format!("{} {} lost their headquarters.", info,
+                                               names.join(", "));
+                                s.visitors.extend(evicted);
+                            }
+                            if self.verbose { println!("{} (step {})", info, s.age) }
+                            self.hist.add_entry(s.age, info);
                         }
-                    },
-                    Rolled::Grow(ref step, ref area) => {
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).map(|b| {
-                                    b.borrow_mut().boosts.grow *= step.clone(); })
-                            },
-                            effects::Area::Quarter(ref qts) => {
-                                self.rand_quarter(&qts).map(|q| {
-                                    q.borrow_mut().boosts.grow *= step.clone(); })
-                            },
-                            effects::Area::Sett => {
-                                self.sett.as_ref().map(|s| {
-                                    for q in s.qrtrs.iter() {
-                                        q.borrow_mut().boosts.grow *= step.clone();
-                                    }
-                                })
-                            },
+                        for (e, origin) in Manager::fire_events(s, &self.datafiles, &emap,
+                                                                 &mut self.rng) {
+                            let desc = e.describe(origin.as_ref(), &s.name);
+                            println!("{} (step {})", desc, s.age);
+                            self.hist.add_entry(s.age, desc);
+                            self.schedule.add(s.age, events::Scheduled {
+                                event: e,
+                                origin,
+                                after: None,
+                            });
                         }
-                    },
-                    Rolled::Build(ref step, ref area) => {
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).map(|b| {
-                                    b.borrow_mut().boosts.build *= step.clone(); })
-                            },
-                            effects::Area::Quarter(ref qts) => {
-                                self.rand_quarter(&qts).map(|q| {
-                                    q.borrow_mut().boosts.build *= step.clone(); })
-                            },
-                            effects::Area::Sett => {
-                                self.sett.as_ref().map(|s| {
-                                    for q in s.qrtrs.iter() {
-                                        q.borrow_mut().boosts.build *= step.clone();
-                                    }
-                                })
-                            },
+                        Manager::report_governors(s, &mut self.hist, &govs, self.automate,
+                                                  &mut self.rng);
+                        // remove the heroes who died this step
+                        let age = s.age;
+                        let (graves, lost) = s.bury_dead();
+                        for g in graves {
+                            let h = g.hero.borrow();
+                            let obit = format!("{}, a level {} {} {}, has died {}, aged {}.",
+                                               h.name, h.level, h.race, h.class, g.cause, h.age);
+                            if self.verbose { println!("{} (step {})", obit, age) }
+                            self.hist.add_entry(age, obit);
                         }
+                        for (hero, item) in lost {
+                            let info = format!("{}, left by {}, was lost for want of a \
+                                                building to keep it.", item.borrow(), hero);
+                            if self.verbose { println!("{} (step {})", info, age) }
+                            self.hist.add_entry(age, info);
+                        }
+                        emap.founding
                     },
-                    Rolled::Gold(ref step, ref bonus) => {
-                        self.sett.as_mut().map(|s| {
-                            s.boosts.gold_bonus += bonus.clone();
-                            s.boosts.gold *= step.clone(); })
-                    },
-                    Rolled::Hero(level, ref class, ref area) => {
-                        let hero = self.create_hero(level, &class);
-                        hero.and_then(|h| {
-                            match *area {
-                                effects::Area::Building(ref bts) => {
-                                    self.rand_building(&bts).and_then(|b| {
-                                        // TODO: loses possible error
-                                        b.borrow_mut().add_occupant(h).ok() })
-                                },
-                                _ => None,
-                            }
-                        })
-                    },
-                    Rolled::Item(value, kind, power, ref area) => {
-                        // create item
-                        let name = self.namefiles.get_item();
+                    None => false,
+                };
+                self.collect_treasure();
+                self.house_visitors();
+                if founding {
+                    self.found_quarter();
+                }
+                self.perform_events();
+            }
+            Ok(())
+        } else {
+            Err(Error::NoSett)
+        };
+        stepped.unwrap_or_else(|e| println!("Failed to perform step: {}", e))
+    }
+
+    /// Record in the history any of the given governors who have left office
+    /// (by dying or by taking up another activity), and the outbreak of
+    /// anarchy if no one governs any longer. If promote is true (in auto
+    /// mode), a successor is then promoted (see promote_successor).
+    fn report_governors<R: Rng>(s: &mut sett::Sett, hist: &mut history::History,
+                                govs: &[Rc<RefCell<people::Hero>>], promote: bool, rng: &mut R)
+    {
+        for g in govs.iter() {
+            let g = g.borrow();
+            match g.activity {
+                people::Activity::Governing => (),
+                people::Activity::Dying(ref cause) => hist.add_entry(
+                    s.age, format!("Governor {} has died {}.", g.name, cause)),
+                _ => hist.add_entry(
+                    s.age, format!("Governor {} has stepped down ({}).", g.name, g.activity)),
+            }
+        }
+        if !govs.is_empty() && s.get_governors().is_empty() {
+            println!("With no one governing, anarchy has broken out in {}! (step {})",
+                     s.name, s.age);
+            hist.add_entry(s.age, format!(
+                "With no one governing, anarchy has broken out in {}!", s.name));
+            if promote {
+                Manager::promote_successor(s, hist, rng);
+            }
+        }
+    }
+
+    /// Promote a random working hero who is able to govern to govern the
+    /// sett. Return true if one was found.
+    fn promote_successor<R: Rng>(s: &mut sett::Sett, hist: &mut history::History, rng: &mut R)
+        -> bool
+    {
+        let able = s.get_heroes().into_iter().map(|(_, _, h)| h)
+            .filter(|h| {
+                let h = h.borrow();
+                h.activity == people::Activity::Working && h.act_boost("Governing") > 0.0
+            }).collect::<Vec<_>>();
+        match rng.choose(&able) {
+            Some(h) => {
+                h.borrow_mut().make_governor();
+                hist.add_entry(s.age, format!("{} was promoted to govern {}.",
+                                              h.borrow().name, s.name));
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Perform every event due on the current step (see activate_event), in
+    /// the order they were scheduled. Follow-ups are only performed if they
+    /// can still fire, and are recorded in the history when they do.
+    fn perform_events(&mut self) {
+        let due = match self.sett {
+            Some(ref s) => self.schedule.take_due(s.age),
+            None => return,
+        };
+        for d in due {
+            if let Some((ref after, step)) = d.after {
+                let s = match self.sett {
+                    Some(ref mut s) => s,
+                    None => return,
+                };
+                if !d.event.can_fire(s) {
+                    continue;
+                }
+                s.fired.insert(d.event.name.clone(), s.age);
+                let desc = format!("{} (following the {} on step {})",
+                                   d.event.describe(d.origin.as_ref(), &s.name),
+                                   after.to_lowercase(), step);
+                println!("{} (step {})", desc, s.age);
+                self.hist.add_entry(s.age, desc);
+            }
+            let name = d.event.name.clone();
+            if let Err(e) = self.activate_event(d) {
+                if self.verbose { println!("Failed to perform {}: {}", name, e) }
+            }
+        }
+    }
+
+    /// Roll the events of the given EventMap, keeping those which can fire in
+    /// the given sett (see Event::can_fire) and then one random event of
+    /// each exclusion group. Mark the kept events as fired on this step.
+    fn fire_events<R: Rng>(s: &mut sett::Sett, datafiles: &libdata::DataFiles,
+                           emap: &events::EventMap, rng: &mut R)
+        -> Vec<(Rc<events::Event>, Option<events::Origin>)>
+    {
+        let mut fired = emap.rand_events(rng).into_iter()
+            .filter_map(|(name, origin)| {
+                datafiles.events.iter().find(|e| e.name == name && e.can_fire(s))
+                    .map(|e| (e.clone(), origin))
+            })
+            .collect::<Vec<_>>();
+        let groups = fired.iter().flat_map(|f| f.0.exclusive.iter().cloned())
+            .collect::<BTreeSet<_>>();
+        for g in groups {
+            let members = fired.iter().enumerate()
+                .filter(|&(_, f)| f.0.exclusive.contains(&g))
+                .map(|(i, _)| i).collect::<Vec<_>>();
+            if let Some(&keep) = rng.choose(&members) {
+                fired = fired.into_iter().enumerate()
+                    .filter(|&(i, ref f)| i == keep || !f.0.exclusive.contains(&g))
+                    .map(|(_, f)| f).collect();
+            }
+        }
+        for f in fired.iter() {
+            s.fired.insert(f.0.name.clone(), s.age);
+        }
+        fired
+    }
+
+    /// Pay out the treasure of every hero returning from an adventure:
+    /// a lump of gold scaled by their level, and possibly a rare item which
+    /// is kept in their headquarters (or carried, if it cannot be kept there).
+    fn collect_treasure(&mut self) {
+        if let Some(ref mut s) = self.sett {
+            let mut hauls = vec![];
+            for (q, b) in s.get_buildings() {
+                for h in b.borrow().occupants.iter() {
+                    if let people::Activity::Treasure(lvl) = h.borrow().activity {
+                        hauls.push((Some(q.clone()), Some(b.clone()), h.clone(), lvl));
+                    }
+                }
+            }
+            for h in s.visitors.iter() {
+                if let people::Activity::Treasure(lvl) = h.borrow().activity {
+                    hauls.push((None, None, h.clone(), lvl));
+                }
+            }
+            for (q, b, h, lvl) in hauls {
+                let gold = dice::roll(&format!("{}d20 * 5", lvl), &mut self.rng).unwrap_or(0) as f64
+                    * h.borrow().act_boost("Treasure");
+                let gold = gold.round();
+                s.transact(ledger::Category::Treasure, q, gold);
+                let mut info = format!("{} returned from an adventure with {} gold in treasure",
+                                       h.borrow().name, gold);
+                if self.rng.gen_weighted_bool(TREASURE_ITEM_ODDS) {
+                    // the item is made like one from an Item effect,
+                    // of a kind the hero's class can use
+                    let datafiles = &self.datafiles;
+                    let kinds = h.borrow().class.items.iter()
+                        .filter_map(|k| datafiles.get_item_kind(k))
+                        .collect::<Vec<_>>();
+                    if let Some(kind) = self.rng.choose(&kinds).cloned() {
+                        let value = dice::roll(&format!("{}d20 * 10", lvl), &mut self.rng)
+                            .unwrap_or(0) as f64;
+                        let power = kind.roll_power(0.05 * lvl as f64, &mut self.rng);
+                        let name = self.namefiles.get_item(&mut self.rng);
                         let item = Rc::new(RefCell::new(
                                 items::Item::new(&name, kind, power, value)));
-                        // put in area
-                        match *area {
-                            effects::Area::Building(ref bts) => {
-                                self.rand_building(&bts).and_then(|b| {
-                                    // TODO: loses possible error
-                                    b.borrow_mut().add_item(item).ok() })
-                            },
-                            _ => None,
+                        let kept = match b {
+                            Some(b) => b.borrow_mut().add_item(item.clone()).is_ok(),
+                            None => false,
+                        };
+                        if !kept {
+                            h.borrow_mut().items.push(item.clone());
                         }
-                    },
-                }.ok_or(Error::Event)
-            }).collect::<Result<Vec<_>>>()
+                        info = format!("{} and {}", info, item.borrow());
+                    }
+                }
+                if self.verbose { println!("{} (step {})", info, s.age) }
+                self.hist.add_entry(s.age, format!("{}.", info));
+            }
+        }
+    }
+
+    /// Perform the effects of the given event on the sett, then schedule any
+    /// of its follow-ups. Every effect is attempted, even if some fail.
+    /// If successful, return a Result<Vec<()>> with len == effects performed.
+    /// If any effect fails, return Error::Effects naming the kinds of those
+    /// that failed, or Error::Dice (performing nothing) if the dice of any
+    /// effect cannot be rolled.
+    pub fn activate_event(&mut self, d: events::Scheduled) -> Result<Vec<()>> {
+        use effects::RolledEffect as Rolled;
+        let events::Scheduled { event: e, origin, .. } = d;
+        // the origin may have been demolished since the event fired
+        let site = origin.as_ref().and_then(|o| self.find_origin(o));
+        let rolled = e.activate(origin.as_ref().filter(|_| site.is_some()), &mut self.rng)
+            .map_err(Error::Dice)?;
+        let mut performed = vec![];
+        let mut failed = vec![];
+        for (r, effect) in rolled.iter().zip(e.effects.iter()) {
+            let origin = site.as_ref();
+            let done = match *r {
+                Rolled::Kill(frac, ref area) => {
+                    let cause = format!("in the {}", e.name.to_lowercase());
+                    self.kill(frac, area, origin, &cause)
+                },
+                Rolled::Damage(frac, ref area) => self.damage(frac * 100.0, area, origin),
+                Rolled::Riot(prod, steps, ref area) => {
+                    use effects::Target::*;
+                    let mods = [Grow, Build, Gold].iter()
+                        .map(|t| effects::Modifier::new("Riot", &e.name, *t,
+                                                        effects::Combine::Mul, prod, steps))
+                        .collect();
+                    self.add_modifiers(mods, area, origin)
+                },
+                Rolled::Grow(boost, ref area) => {
+                    let m = effects::Modifier::new("Growth", &e.name, effects::Target::Grow,
+                                                   effects::Combine::Mul, boost, 1);
+                    self.add_modifiers(vec![m], area, origin)
+                },
+                Rolled::Build(boost, ref area) => {
+                    let m = effects::Modifier::new("Construction", &e.name,
+                                                   effects::Target::Build,
+                                                   effects::Combine::Mul, boost, 1);
+                    self.add_modifiers(vec![m], area, origin)
+                },
+                Rolled::Gold(boost, steps, bonus) => {
+                    let mut mods = vec![
+                        effects::Modifier::new("Windfall", &e.name, effects::Target::Gold,
+                                               effects::Combine::Add, bonus, 1)];
+                    // a boost of 1 or less (e.g. 0 for a one-off windfall)
+                    // never lowers income
+                    if boost > 1.0 {
+                        mods.push(effects::Modifier::new("Trade", &e.name, effects::Target::Gold,
+                                                         effects::Combine::Mul, boost, steps));
+                    }
+                    self.add_modifiers(mods, &effects::Area::Sett, origin)
+                },
+                Rolled::Hero(level, ref class) => {
+                    let hero = class.as_ref().and_then(|c| self.create_hero(level, c));
+                    hero.map(|h| self.house_hero(h))
+                },
+                Rolled::Item(value, ref kind, magical) => {
+                    // create item
+                    let kind = kind.as_ref().and_then(|k| self.datafiles.get_item_kind(k));
+                    kind.map(|k| {
+                        let power = k.roll_power(magical, &mut self.rng);
+                        let name = self.namefiles.get_item(&mut self.rng);
+                        items::Item::new(&name, k, power, value)
+                    }).and_then(|item| {
+                        // put in the origin if it can keep it, or else
+                        // in a building that can
+                        let b = origin.map(|o| o.1.clone())
+                            .filter(|b| b.borrow().can_hold(&item).is_ok());
+                        b.or_else(|| self.rand_building_for(&item)).and_then(|b| {
+                            b.borrow_mut().add_item(Rc::new(RefCell::new(item))).ok()
+                        })
+                    })
+                },
+            };
+            match done {
+                Some(x) => performed.push(x),
+                None => failed.push(effect.kind().to_string()),
+            }
+        }
+        self.schedule_follow_ups(&e, origin);
+        if failed.is_empty() {
+            Ok(performed)
         } else {
-            Ok(vec!())
+            Err(Error::Effects(failed))
+        }
+    }
+
+    /// Roll for the follow-ups of the given event, which was just activated,
+    /// and schedule those which follow to fire where it happened.
+    fn schedule_follow_ups(&mut self, e: &events::Event, origin: Option<events::Origin>) {
+        let step = match self.sett {
+            Some(ref s) => s.age,
+            None => return,
+        };
+        for f in e.roll_follow_ups(&mut self.rng) {
+            let next = self.datafiles.events.iter().find(|x| x.name == f.event);
+            if let Some(next) = next {
+                self.schedule.add(step + f.delay.max(1), events::Scheduled {
+                    event: next.clone(),
+                    origin: origin.clone(),
+                    after: Some((e.name.clone(), step)),
+                });
+            }
+        }
+    }
+
+    /// Find the building (with its quarter) at the given origin of an
+    /// event, if it is still standing.
+    fn find_origin(&self, origin: &events::Origin) -> Option<Sited> {
+        let s = self.sett.as_ref()?;
+        let q = s.find_quarter(&origin.quarter)?;
+        let b = q.borrow().find_building(&origin.building)?;
+        if b.borrow().cond == buildings::BldgCond::Ruined {
+            return None;
+        }
+        Some((q, b))
+    }
+
+    /// Return the quarters (and their buildings) struck by an effect on the
+    /// given area (see effects::Area), where the event fired at the given
+    /// source building (if known). Areas relative to an unknown source
+    /// strike nothing.
+    fn struck_area(&mut self, area: &effects::Area, origin: Option<&Sited>) -> Struck {
+        use effects::Area;
+        let s = match self.sett {
+            Some(ref s) => s,
+            None => return vec![],
+        };
+        let rng = &mut self.rng;
+        let whole = |q: &Rc<RefCell<quarters::Quarter>>| (q.clone(), q.borrow().bldgs.clone());
+        match *area {
+            Area::Building(ref names) => Manager::rand_building(s, rng, |b| {
+                (names.contains(&b.plan.name) || names.contains(&b.plan.btype))
+                    && b.cond != buildings::BldgCond::Ruined
+            }),
+            Area::Condition(max) => Manager::rand_building(s, rng, |b| match b.cond {
+                buildings::BldgCond::InUse(c) => c <= max,
+                _ => false,
+            }),
+            Area::Quarter(ref names) => Manager::rand_quarter(s, rng, |q| {
+                names.is_empty() || names.contains(&q.qtype.name)
+                    || q.bldgs.iter().any(|b| names.contains(&b.borrow().plan.name))
+            }),
+            Area::Race(ref races) => Manager::rand_quarter(s, rng, |q| {
+                races.contains(&q.race.name)
+            }),
+            Area::Source => origin.iter().map(|o| (o.0.clone(), vec![o.1.clone()])).collect(),
+            Area::SourceQuarter => origin.iter().map(|o| whole(&o.0)).collect(),
+            Area::Adjacent => {
+                let i = origin.and_then(|o| s.qrtrs.iter().position(|x| Rc::ptr_eq(x, &o.0)));
+                match i {
+                    Some(i) => s.qrtrs.iter().enumerate()
+                        .filter(|&(j, _)| j + 1 == i || j == i + 1)
+                        .map(|(_, q)| whole(q)).collect(),
+                    None => vec![],
+                }
+            },
+            Area::Sett => s.qrtrs.iter().map(whole).collect(),
+        }
+    }
+
+    /// Add the given modifiers to the buildings or quarters struck in the
+    /// given area (see struck_area), or to the whole sett.
+    fn add_modifiers(&mut self, mods: Vec<effects::Modifier>, area: &effects::Area,
+                     origin: Option<&Sited>) -> Option<()>
+    {
+        let add = |boosts: &mut effects::EffectFlags| {
+            for m in mods.iter() {
+                boosts.add(m.clone());
+            }
+        };
+        if let effects::Area::Sett = *area {
+            return self.sett.as_mut().map(|s| add(&mut s.boosts));
+        }
+        let struck = self.struck_area(area, origin);
+        if struck.is_empty() {
+            return None;
+        }
+        for (q, bs) in struck {
+            if area.is_building() {
+                for b in bs {
+                    add(&mut b.borrow_mut().boosts);
+                }
+            } else {
+                add(&mut q.borrow_mut().boosts);
+            }
+        }
+        Some(())
+    }
+
+    /// Kill the given fraction (between 0 and 1) of the population in the
+    /// given area, along with any heroes at home there who are struck
+    /// (see Hero::strike). Record the casualties in the history, along with
+    /// any governor who was killed (see report_governors).
+    fn kill(&mut self, frac: f64, area: &effects::Area, origin: Option<&Sited>, cause: &str)
+        -> Option<()>
+    {
+        let struck = self.struck_area(area, origin);
+        if struck.is_empty() {
+            return None;
+        }
+        let s = self.sett.as_mut()?;
+        let govs = s.get_governors();
+        let mut heroes = vec![];
+        if let effects::Area::Sett = *area {
+            let rng = &mut self.rng;
+            heroes.extend(s.visitors.iter()
+                          .filter(|h| h.borrow_mut().strike(frac, cause, rng))
+                          .cloned());
+        }
+        for (q, bs) in struck {
+            let dead = q.borrow_mut().kill(frac);
+            for b in bs {
+                heroes.extend(b.borrow_mut().kill_occupants(frac, cause, &mut self.rng));
+            }
+            let info = format!("{} people died {} in the {} Quarter.", dead, cause, q.borrow().name);
+            if self.verbose { println!("{}", info) }
+            self.hist.add_entry(s.age, info);
+        }
+        if !heroes.is_empty() {
+            let names = heroes.iter().map(|h| h.borrow().name.clone()).collect::<Vec<_>>();
+            self.hist.add_entry(s.age, format!("Among the dead were {}.", names.join(", ")));
+        }
+        Manager::report_governors(s, &mut self.hist, &govs, self.automate, &mut self.rng);
+        Some(())
+    }
+
+    /// Damage every building in the given area by the given amount of
+    /// condition (see Building::damage). Heroes driven out of ruined
+    /// buildings stay in town until they find a new headquarters.
+    /// Record the damage in the history.
+    fn damage(&mut self, amount: f64, area: &effects::Area, origin: Option<&Sited>)
+        -> Option<()>
+    {
+        let struck = self.struck_area(area, origin);
+        if struck.is_empty() {
+            return None;
+        }
+        if amount <= 0.0 {
+            return Some(());
         }
+        let s = self.sett.as_mut()?;
+        for (q, bs) in struck {
+            for b in bs {
+                let was_in_use = b.borrow().cond.is_in_use();
+                let evicted = b.borrow_mut().damage(amount);
+                let b = b.borrow();
+                let mut info = match b.cond {
+                    buildings::BldgCond::InProgress(_) =>
+                        format!("Construction of the {} in the {} Quarter was set back to the start.",
+                                b.name, q.borrow().name),
+                    buildings::BldgCond::InUse(n) =>
+                        format!("The {} in the {} Quarter was damaged ({}).",
+                                b.name, q.borrow().name, buildings::BldgCond::InUse(n)),
+                    buildings::BldgCond::Ruined if was_in_use =>
+                        format!("The {} in the {} Quarter was ruined.", b.name, q.borrow().name),
+                    buildings::BldgCond::Ruined => continue,
+                };
+                if !evicted.is_empty() {
+                    let names = evicted.iter().map(|h| h.borrow().name.clone()).collect::<Vec<_>>();
+                    info = format!("{} {} lost their headquarters.", info, names.join(", "));
+                    s.visitors.extend(evicted);
+                }
+                if self.verbose { println!("{}", info) }
+                self.hist.add_entry(s.age, info);
+            }
+        }
+        Some(())
     }
 
     /// Initialize a new hero with a random name and race.
     /// Set the hero's level based on the given level integer.
     /// Select the hero's class based on the given classname string.
-    fn create_hero(&self, lvl: i32, classname: &str) -> Option<Rc<RefCell<people::Hero>>> {
-        let name = self.namefiles.get_hero();
+    /// Return None if there is no such class, or it lists no declared race.
+    fn create_hero(&mut self, lvl: i32, classname: &str) -> Option<Rc<RefCell<people::Hero>>> {
+        let name = self.namefiles.get_hero(&mut self.rng);
         //println!("Class: {}", classname);
         let class = self.datafiles.classes.iter()
             .find(|c| c.name == classname).map(|c| c.clone());
-        class.map(|c| {
-            let race = {
-                let racename = rand::thread_rng().choose(&c.races)
-                    .expect("Unable to create hero: the created class has no races listed!");
-                //println!("Race: {}", racename);
-                racename.parse::<people::Race>()
-                    .unwrap_or_else(|_| {
-                        println!("Unable to create hero: \
-                                 race {} of class {} is invalid!", racename, c.name);
-                        panic!("Invalid race provided!")
-                    })
-            };
-            Rc::new(RefCell::new(people::Hero::new(&name, lvl, race, c)))
+        let rng = &mut self.rng;
+        let datafiles = &self.datafiles;
+        class.and_then(|c| {
+            let race = rng.choose(&c.races).and_then(|r| datafiles.get_race(r))?;
+            Some(Rc::new(RefCell::new(people::Hero::new(&name, lvl, race, c))))
         })
     }
 
-    /// Return a random quarter in the settlement.
-    fn rand_quarter(&self, qtypes: &[quarters::QType]) -> Option<Rc<RefCell<quarters::Quarter>>> {
-        match self.sett {
-            Some(ref s) => {
-                let filtered = s.qrtrs.clone().into_iter()
-                    .filter(|q| qtypes.contains(&q.borrow().qtype))
-                    .map(|q| q.clone()).collect::<Vec<_>>();
-                rand::thread_rng().choose(&filtered).cloned()
-            },
-            None => None,
+    /// Place a hero in a random building their class can occupy.
+    /// If there is none, the hero stays in town as a visitor until one is
+    /// built (see house_visitors()).
+    fn house_hero(&mut self, h: Rc<RefCell<people::Hero>>) {
+        if let Some(ref mut s) = self.sett {
+            let hq = s.rand_headquarters(&h.borrow().class, &mut self.rng);
+            let name = h.borrow().name.clone();
+            match hq {
+                Some(ref b) if b.borrow_mut().add_occupant(h.clone()).is_ok() => {
+                    self.hist.add_entry(s.age, format!("{} has made the {} their headquarters.",
+                                                       name, b.borrow().name));
+                },
+                _ => {
+                    s.visitors.push(h);
+                    self.hist.add_entry(s.age, format!("{} has arrived in {}, but found no \
+                                                        headquarters and is staying in town.",
+                                                       name, s.name));
+                },
+            }
+        }
+    }
+
+    /// Move any visitors into a building their class can occupy, if one is
+    /// now available.
+    fn house_visitors(&mut self) {
+        if let Some(ref mut s) = self.sett {
+            let visitors = s.visitors.drain(..).collect::<Vec<_>>();
+            for h in visitors {
+                let hq = s.rand_headquarters(&h.borrow().class, &mut self.rng);
+                match hq {
+                    Some(ref b) if b.borrow_mut().add_occupant(h.clone()).is_ok() => {
+                        self.hist.add_entry(s.age, format!(
+                            "{} has made the {} their headquarters.",
+                            h.borrow().name, b.borrow().name));
+                    },
+                    _ => s.visitors.push(h),
+                }
+            }
         }
     }
 
-    /// Return a random building in the settlement.
-    fn rand_building(&self, btypes: &[quarters::QType]) -> Option<Rc<RefCell<buildings::Building>>> {
+    /// Return a random quarter of the given sett matching the given
+    /// predicate, along with all of its buildings.
+    fn rand_quarter<R, F>(s: &sett::Sett, rng: &mut R, pred: F) -> Struck
+        where R: Rng, F: Fn(&quarters::Quarter) -> bool
+    {
+        let filtered = s.qrtrs.iter()
+            .filter(|q| pred(&q.borrow()))
+            .map(|q| (q.clone(), q.borrow().bldgs.clone()))
+            .collect::<Vec<_>>();
+        rng.choose(&filtered).cloned().into_iter().collect()
+    }
+
+    /// Return a random building of the given sett matching the given
+    /// predicate, along with its quarter.
+    fn rand_building<R, F>(s: &sett::Sett, rng: &mut R, pred: F) -> Struck
+        where R: Rng, F: Fn(&buildings::Building) -> bool
+    {
+        let filtered = s.qrtrs.iter()
+            .flat_map(|q| q.borrow().bldgs.iter()
+                      .filter(|b| pred(&b.borrow()))
+                      .map(|b| (q.clone(), vec![b.clone()]))
+                      .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        rng.choose(&filtered).cloned().into_iter().collect()
+    }
+
+    /// Return a random building in the settlement that can hold the given
+    /// item (see Building::can_hold).
+    fn rand_building_for(&mut self, item: &items::Item)
+        -> Option<Rc<RefCell<buildings::Building>>>
+    {
         match self.sett {
             Some(ref s) => {
                 let filtered = s.qrtrs.iter()
                     .flat_map(|q| q.borrow().bldgs.clone().into_iter())
-                    .filter(|b| btypes.contains(&b.borrow().plan.btype)
-                            // make sure building is in use
-                            && enum_match!(b.borrow().cond, buildings::BldgCond::InUse(_)))
-                    .map(|b| b.clone()).collect::<Vec<_>>();
-                rand::thread_rng().choose(&filtered).cloned()
+                    .filter(|b| b.borrow().can_hold(item).is_ok())
+                    .collect::<Vec<_>>();
+                self.rng.choose(&filtered).cloned()
             },
             None => None,
         }
@@ -724,10 +1618,535 @@ impl Manager {
                 },
                 //TODO: allow second term to control history date(s)
                 "history" => println!("{}", self.hist.show(None)),
-                "queue" => println!("{:?}", self.queue),
+                "ledger" => {
+                    let steps = term2.and_then(|t| t.parse::<i32>().ok());
+                    self.sett.as_ref().map(|s| println!("{}", s.ledger.show(s.age, steps)))
+                        .unwrap_or_else(|| println!("Target to print not found."));
+                },
+                "effects" => {
+                    self.sett.as_ref().map(|s| {
+                        let effects = s.get_effects();
+                        if effects.is_empty() {
+                            println!("No effects are active.")
+                        }
+                        for (area, m) in effects.iter() {
+                            println!("{}: {}", area, m)
+                        }
+                    }).unwrap_or_else(|| println!("Target to print not found."));
+                },
+                "dead" => {
+                    self.sett.as_ref().map(|s| {
+                        if s.graveyard.is_empty() {
+                            println!("No heroes have died yet.")
+                        }
+                        for g in s.graveyard.iter() {
+                            dev_print!(self.dev, g)
+                        }
+                    }).unwrap_or_else(|| println!("Target to print not found."));
+                },
+                "schedule" => println!("{}", self.schedule),
+                "event" => {
+                    let events = &self.datafiles.events;
+                    let names = events.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+                    prompts::prechoose(&names, term2).ok()
+                        .map(|i| println!("{}", events[i]))
+                        .unwrap_or_else(|| println!("Target to print not found."));
+                },
                 _ => (),
             },
             None => print_opt!(self.dev, self.sett),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a manager holding a new sett built automatically from the
+    /// given seed.
+    fn manager(seed: u64) -> Manager {
+        let pl = libdata::PathList::from_dirs("lib/data/", "lib/names/").unwrap();
+        let mut m = Manager::new(&pl, false, Some(seed)).unwrap();
+        m.toggle_auto();
+        m.build_sett(Some(String::from("Testville")), true);
+        m
+    }
+
+    fn sett(m: &Manager) -> &sett::Sett {
+        m.sett.as_ref().unwrap()
+    }
+
+    fn logged(m: &Manager, text: &str) -> bool {
+        m.hist.entries.iter().any(|e| e.info.contains(text))
+    }
+
+    /// Add the named building, in use at the given condition, to the sett's
+    /// quarter of the given index and return it.
+    fn erect(m: &Manager, i: usize, name: &str, cond: f64) -> Rc<RefCell<buildings::Building>> {
+        let plan = m.datafiles.plans.iter().find(|p| p.name == name).unwrap().clone();
+        let b = Rc::new(RefCell::new(buildings::Building::new(plan)));
+        b.borrow_mut().cond = buildings::BldgCond::InUse(cond);
+        sett(m).qrtrs[i].borrow_mut().bldgs.push(b.clone());
+        b
+    }
+
+    /// Add a Barracks to the sett's first quarter, housing a new fighter,
+    /// and return the building.
+    fn barracks(m: &mut Manager) -> Rc<RefCell<buildings::Building>> {
+        let b = erect(m, 0, "Barracks", 100.0);
+        let hero = m.create_hero(1, "Fighter").unwrap();
+        b.borrow_mut().add_occupant(hero).unwrap();
+        b
+    }
+
+    /// Return the tax collected on the last step per member of the population.
+    fn tax_rate(m: &Manager) -> f64 {
+        let s = sett(m);
+        s.ledger.entries.iter()
+            .filter(|e| e.date == s.age && e.category == ledger::Category::Tax)
+            .map(|e| e.amount).sum::<f64>() / s.pop
+    }
+
+    #[test]
+    fn returning_heroes_bring_treasure() {
+        let mut m = manager(1);
+        let hero = m.create_hero(3, "Fighter").unwrap();
+        hero.borrow_mut().activity = people::Activity::Treasure(3);
+        m.house_hero(hero.clone());
+        let gold = sett(&m).gold;
+        m.collect_treasure();
+        let s = sett(&m);
+        let treasure = s.ledger.entries.iter()
+            .filter(|e| e.category == ledger::Category::Treasure)
+            .map(|e| e.amount).sum::<f64>();
+        // 3d20 * 5 gold, times the hero's treasure boost
+        let boost = hero.borrow().act_boost("Treasure");
+        assert!(treasure >= (15.0 * boost).round() && treasure <= (300.0 * boost).round());
+        assert_eq!(s.gold, gold + treasure);
+        assert!(logged(&m, &format!("{} returned from an adventure with {} gold",
+                                    hero.borrow().name, treasure)));
+    }
+
+    #[test]
+    fn governor_killed_by_event_is_reported() {
+        let mut m = manager(1);
+        m.automate = false;
+        let gov = sett(&m).get_governors()[0].borrow().name.clone();
+        m.kill(1.0, &effects::Area::Sett, None, "in the test");
+        assert!(logged(&m, &format!("Governor {} has died in the test.", gov)));
+        assert!(logged(&m, "anarchy has broken out in Testville"));
+    }
+
+    #[test]
+    fn anarchy_lowers_production() {
+        let mut m = manager(1);
+        m.automate = false;
+        m.step(1);
+        assert!((tax_rate(&m) - 0.01).abs() < 1e-9);
+        // the governor steps down, leaving no one in office
+        sett(&m).get_governors()[0].borrow_mut().activity = people::Activity::Working;
+        m.step(1);
+        assert!(sett(&m).get_governors().is_empty());
+        assert!((tax_rate(&m) - 0.01 * ANARCHY_PROD).abs() < 1e-9);
+    }
+
+    #[test]
+    fn auto_mode_promotes_a_successor() {
+        let mut m = manager(1);
+        let hero = m.create_hero(1, "Fighter").unwrap();
+        m.house_hero(hero.clone());
+        sett(&m).get_governors()[0].borrow_mut().activity = people::Activity::Resting(5);
+        m.step(1);
+        let name = hero.borrow().name.clone();
+        assert!(logged(&m, &format!("{} was promoted to govern Testville.", name)));
+    }
+
+    #[test]
+    fn manual_mode_leaves_the_office_empty() {
+        let mut m = manager(1);
+        m.automate = false;
+        let hero = m.create_hero(1, "Fighter").unwrap();
+        m.house_hero(hero);
+        sett(&m).get_governors()[0].borrow_mut().activity = people::Activity::Resting(5);
+        m.step(1);
+        assert!(!logged(&m, "was promoted to govern"));
+    }
+
+    #[test]
+    fn heroes_outlive_their_ruined_headquarters() {
+        let mut m = manager(1);
+        let hq = barracks(&mut m);
+        let hero = hq.borrow().occupants[0].clone();
+        hq.borrow_mut().cond = buildings::BldgCond::InUse(0.0);
+        m.step(1);
+        assert_eq!(hq.borrow().cond, buildings::BldgCond::Ruined);
+        assert!(sett(&m).get_heroes().iter().any(|(_, _, h)| Rc::ptr_eq(h, &hero)));
+        let name = hero.borrow().name.clone();
+        assert!(logged(&m, &format!("fell into ruin. {}", name)));
+    }
+
+    #[test]
+    fn ruins_are_restored_for_a_fee() {
+        let mut m = manager(1);
+        let b = barracks(&mut m);
+        b.borrow_mut().damage(100.0);
+        m.sett.as_mut().unwrap().gold = 1000.0;
+        let cost = b.borrow().get_rep_cost(None).unwrap();
+        let (gold, q) = (sett(&m).gold, sett(&m).qrtrs[0].borrow().name.clone());
+        m.repair_building(Some(String::from("Barracks")), Some(q), None);
+        assert_eq!(b.borrow().cond, buildings::BldgCond::InProgress(0.0));
+        assert_eq!(sett(&m).gold, gold - cost);
+        assert!(logged(&m, "Restoration of the ruins of the Barracks"));
+    }
+
+    #[test]
+    fn demolished_buildings_leave_their_occupants_in_town() {
+        let mut m = manager(1);
+        let b = barracks(&mut m);
+        let hero = b.borrow().occupants[0].clone();
+        let cost = b.borrow().get_demolish_cost();
+        let (gold, q) = (sett(&m).gold, sett(&m).qrtrs[0].borrow().name.clone());
+        m.demolish_building(Some(String::from("Barracks")), Some(q));
+        let s = sett(&m);
+        assert!(s.get_buildings().iter().all(|(_, b)| b.borrow().name != "Barracks"));
+        assert!(s.visitors.iter().any(|h| Rc::ptr_eq(h, &hero)));
+        assert_eq!(s.gold, gold - cost);
+        assert!(s.ledger.entries.iter()
+                .any(|e| e.category == ledger::Category::Construction && e.amount == -cost));
+    }
+
+    #[test]
+    fn repairs_are_paid_for_immediately() {
+        let mut m = manager(1);
+        let b = barracks(&mut m);
+        b.borrow_mut().cond = buildings::BldgCond::InUse(40.0);
+        m.sett.as_mut().unwrap().gold = 1000.0;
+        let cost = b.borrow().get_rep_cost(Some(25.0)).unwrap();
+        let q = sett(&m).qrtrs[0].borrow().name.clone();
+        m.repair_building(Some(String::from("Barracks")), Some(q), Some(25.0));
+        assert_eq!(b.borrow().cond, buildings::BldgCond::InUse(65.0));
+        assert_eq!(sett(&m).gold, 1000.0 - cost);
+    }
+
+    #[test]
+    fn buildings_in_full_repair_cost_nothing() {
+        let mut m = manager(1);
+        barracks(&mut m);
+        let (gold, entries) = (sett(&m).gold, m.hist.entries.len());
+        let q = sett(&m).qrtrs[0].borrow().name.clone();
+        m.repair_building(Some(String::from("Barracks")), Some(q), None);
+        let s = sett(&m);
+        assert_eq!(s.gold, gold);
+        assert!(s.ledger.entries.iter().all(|e| e.category != ledger::Category::Repairs));
+        assert_eq!(m.hist.entries.len(), entries);
+    }
+
+    #[test]
+    fn repair_all_spends_only_its_budget_on_the_worst_first() {
+        let mut m = manager(1);
+        let worst = barracks(&mut m);
+        let plan = worst.borrow().plan.clone();
+        let other = Rc::new(RefCell::new(buildings::Building::new(plan)));
+        other.borrow_mut().name = String::from("Old Barracks");
+        sett(&m).qrtrs[0].borrow_mut().bldgs.push(other.clone());
+        worst.borrow_mut().cond = buildings::BldgCond::InUse(10.0);
+        other.borrow_mut().cond = buildings::BldgCond::InUse(50.0);
+        m.sett.as_mut().unwrap().gold = 1000.0;
+        // enough for 30 points of repairs
+        let budget = 30.0 * worst.borrow().get_rep_rate();
+        m.repair_all(Some(budget));
+        assert_eq!(worst.borrow().cond, buildings::BldgCond::InUse(40.0));
+        assert_eq!(other.borrow().cond, buildings::BldgCond::InUse(50.0));
+        assert!((sett(&m).gold - (1000.0 - budget)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ledger_accounts_for_every_change_in_gold() {
+        let mut m = manager(1);
+        for _ in 0..5 {
+            let gold = sett(&m).gold;
+            m.step(1);
+            let s = sett(&m);
+            let (income, spending) = s.ledger.totals(s.age, Some(1)).total;
+            assert!((s.gold - gold - (income - spending)).abs() < 1e-9);
+        }
+    }
+
+    /// Found two more quarters, of the given races, after the main one.
+    fn found_quarters(m: &mut Manager, races: &[&str]) {
+        let qtype = m.datafiles.qtypes.iter().find(|qt| !qt.coastal).unwrap().clone();
+        for (i, r) in races.iter().enumerate() {
+            let race = m.datafiles.races.iter().find(|x| x.name == *r).unwrap().clone();
+            m.sett.as_mut().unwrap()
+                .add_quarter(format!("Quarter {}", i + 1), qtype.clone(), race).unwrap();
+        }
+    }
+
+    /// Return the names of the quarters and buildings struck.
+    fn names(struck: &Struck) -> Vec<(String, Vec<String>)> {
+        struck.iter().map(|(q, bs)| (q.borrow().name.clone(),
+                                     bs.iter().map(|b| b.borrow().name.clone()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn buildings_are_struck_by_name_and_condition() {
+        let mut m = manager(1);
+        found_quarters(&mut m, &["Elf"]);
+        let forge = erect(&m, 0, "Forge", 100.0);
+        erect(&m, 1, "Market", 30.0);
+        let by_name = m.struck_area(&effects::Area::Building(vec![String::from("Forge")]), None);
+        assert_eq!(names(&by_name), vec![(String::from("Main"), vec![String::from("Forge")])]);
+        let worn = m.struck_area(&effects::Area::Condition(50.0), None);
+        assert_eq!(names(&worn), vec![(String::from("Quarter 1"), vec![String::from("Market")])]);
+        // ruins are never struck by name
+        forge.borrow_mut().cond = buildings::BldgCond::Ruined;
+        assert!(m.struck_area(&effects::Area::Building(vec![String::from("Forge")]), None)
+                .is_empty());
+    }
+
+    #[test]
+    fn quarters_are_struck_by_race_and_source() {
+        let mut m = manager(1);
+        found_quarters(&mut m, &["Elf", "Dwarf", "Gnome"]);
+        let forge = erect(&m, 2, "Forge", 100.0);
+        erect(&m, 2, "Market", 100.0);
+        let elves = m.struck_area(&effects::Area::Race(vec![String::from("Elf")]), None);
+        assert_eq!(names(&elves), vec![(String::from("Quarter 1"), vec![])]);
+        let origin = (sett(&m).qrtrs[2].clone(), forge);
+        let source = m.struck_area(&effects::Area::Source, Some(&origin));
+        assert_eq!(names(&source), vec![(String::from("Quarter 2"), vec![String::from("Forge")])]);
+        let quarter = m.struck_area(&effects::Area::SourceQuarter, Some(&origin));
+        assert_eq!(quarter[0].1.len(), 2);
+        let adjacent = m.struck_area(&effects::Area::Adjacent, Some(&origin));
+        assert_eq!(names(&adjacent).into_iter().map(|x| x.0).collect::<Vec<_>>(),
+                   vec![String::from("Quarter 1"), String::from("Quarter 3")]);
+        // areas relative to the source strike nothing without one
+        assert!(m.struck_area(&effects::Area::Adjacent, None).is_empty());
+        assert_eq!(m.struck_area(&effects::Area::Quarter(vec![]), None).len(), 1);
+        assert_eq!(m.struck_area(&effects::Area::Sett, None).len(), 4);
+    }
+
+    /// Add an event with no effects to the manager's data files and return it.
+    fn add_event(m: &mut Manager, name: &str, cooldown: i32, exclusive: &[&str],
+                 follow_ups: Vec<events::FollowUp>) -> Rc<events::Event>
+    {
+        let e = Rc::new(events::Event {
+            cooldown,
+            exclusive: exclusive.iter().map(|g| g.to_string()).collect(),
+            follow_ups,
+            ..events::Event::test(name, vec![])
+        });
+        m.datafiles.events.push(e.clone());
+        e
+    }
+
+    /// Add an event with no effects to the manager's data files, returning
+    /// an event map in which it is certain to be rolled.
+    fn certain(m: &mut Manager, name: &str, cooldown: i32, exclusive: &[&str])
+        -> events::EventMap
+    {
+        add_event(m, name, cooldown, exclusive, vec![]);
+        let mut map = events::EventMap::new(0);
+        map.map.insert(name.to_string(), 100.0);
+        map
+    }
+
+    /// Return the names of the events fired from the given event map.
+    fn fire(m: &mut Manager, emap: &events::EventMap) -> Vec<String> {
+        Manager::fire_events(m.sett.as_mut().unwrap(), &m.datafiles, emap, &mut m.rng)
+            .into_iter().map(|(e, _)| e.name.clone()).collect()
+    }
+
+    #[test]
+    fn events_cool_down_after_firing() {
+        let mut m = manager(1);
+        let emap = certain(&mut m, "TestAlarm", 2, &[]);
+        m.sett.as_mut().unwrap().age = 5;
+        assert_eq!(fire(&mut m, &emap), vec![String::from("TestAlarm")]);
+        for age in 6..8 {
+            m.sett.as_mut().unwrap().age = age;
+            assert!(fire(&mut m, &emap).is_empty());
+        }
+        m.sett.as_mut().unwrap().age = 8;
+        assert_eq!(fire(&mut m, &emap), vec![String::from("TestAlarm")]);
+    }
+
+    #[test]
+    fn one_event_of_each_exclusion_group_fires() {
+        let mut m = manager(1);
+        let mut emap = certain(&mut m, "TestFire", 0, &["Disaster"]);
+        emap.map.append(&mut certain(&mut m, "TestFlood", 0, &["Disaster"]).map);
+        emap.map.append(&mut certain(&mut m, "TestRain", 0, &["Weather"]).map);
+        emap.map.append(&mut certain(&mut m, "TestSnow", 0, &["Weather"]).map);
+        emap.map.append(&mut certain(&mut m, "TestAlarm", 0, &[]).map);
+        let mut seen = BTreeSet::new();
+        for age in 1..21 {
+            // no event fires twice on the same step
+            m.sett.as_mut().unwrap().age = age;
+            let fired = fire(&mut m, &emap);
+            let fired = fired.iter().map(|e| e.as_str()).collect::<Vec<_>>();
+            assert!(fired.contains(&"TestAlarm"));
+            assert_eq!(fired.iter().filter(|&&e| e == "TestFire" || e == "TestFlood").count(), 1);
+            assert_eq!(fired.iter().filter(|&&e| e == "TestRain" || e == "TestSnow").count(), 1);
+            seen.extend(fired.into_iter().map(String::from));
+        }
+        // every event gets its turn
+        assert_eq!(seen.len(), 5);
+    }
+
+    fn follow_up(event: &str, chance: f64, delay: i32) -> events::FollowUp {
+        events::FollowUp { event: event.to_string(), chance, delay }
+    }
+
+    #[test]
+    fn follow_ups_fire_after_their_delay() {
+        let mut m = manager(1);
+        add_event(&mut m, "TestBlaze", 0, &[], vec![]);
+        add_event(&mut m, "TestSmoke", 0, &[], vec![]);
+        let spark = add_event(&mut m, "TestSpark", 0, &[],
+                              vec![follow_up("TestBlaze", 100.0, 2),
+                                   follow_up("TestSmoke", 0.0, 1)]);
+        let age = sett(&m).age;
+        m.schedule.add(age, events::Scheduled { event: spark, origin: None, after: None });
+        m.perform_events();
+        assert_eq!(m.schedule.to_string(),
+                   format!("Step {}: TestBlaze (following the testspark on step {})",
+                           age + 2, age));
+        m.sett.as_mut().unwrap().age = age + 2;
+        m.perform_events();
+        assert!(logged(&m, &format!("Testville is tested. (following the testspark on step {})",
+                                    age)));
+        assert_eq!(m.schedule.to_string(), "No events are scheduled.");
+    }
+
+    #[test]
+    fn follow_ups_which_cannot_fire_are_dropped() {
+        let mut m = manager(1);
+        let blaze = add_event(&mut m, "TestBlaze", 5, &[], vec![]);
+        let age = sett(&m).age;
+        m.schedule.add(age, events::Scheduled {
+            event: blaze,
+            origin: None,
+            after: Some((String::from("TestSpark"), age)),
+        });
+        m.sett.as_mut().unwrap().fired.insert(String::from("TestBlaze"), age);
+        m.perform_events();
+        assert!(!logged(&m, "following the testspark"));
+    }
+
+    #[test]
+    fn events_with_bad_dice_fail_without_effect() {
+        let mut m = manager(1);
+        let gold = sett(&m).gold;
+        let gift = |value: &str| effects::Effect::Gold {
+            value: value.to_string(),
+            bonus: 1.0,
+            steps: String::from("1"),
+        };
+        let e = Rc::new(events::Event::test("TestFault", vec![gift("100"), gift("6/0")]));
+        let r = m.activate_event(events::Scheduled { event: e, origin: None, after: None });
+        assert!(enum_match!(r, Err(Error::Dice(dice::DiceError::DivideByZero))));
+        assert_eq!(sett(&m).gold, gold);
+    }
+
+    #[test]
+    fn events_without_an_origin_strike_one_quarter() {
+        let mut m = manager(1);
+        m.automate = false;
+        found_quarters(&mut m, &["Elf", "Dwarf"]);
+        for q in sett(&m).qrtrs.iter() {
+            q.borrow_mut().pop = 1000.0;
+        }
+        let fire = m.datafiles.events.iter().find(|e| e.name == "Fire").unwrap().clone();
+        m.activate_event(events::Scheduled { event: fire, origin: None, after: None }).unwrap();
+        let burnt = sett(&m).qrtrs.iter().filter(|q| q.borrow().pop < 1000.0).count();
+        assert_eq!(burnt, 1);
+    }
+
+    #[test]
+    fn failed_effects_do_not_stop_the_rest() {
+        let mut m = manager(1);
+        let gold = sett(&m).gold;
+        let e = Rc::new(events::Event::test("TestRaid", vec![
+            effects::Effect::Kill {
+                dead: String::from("50"),
+                viralpt: None,
+                area: Some(effects::Area::Race(vec![String::from("Nobody")])),
+            },
+            effects::Effect::Gold {
+                value: String::from("100"),
+                bonus: 1.0,
+                steps: String::from("1"),
+            }]));
+        let r = m.activate_event(events::Scheduled { event: e, origin: None, after: None });
+        match r {
+            Err(Error::Effects(ref kinds)) => assert_eq!(kinds, &vec![String::from("Kill")]),
+            _ => panic!("the failed effect was not reported"),
+        }
+        m.sett.as_mut().unwrap().collect_gold();
+        let s = sett(&m);
+        assert!(s.ledger.entries.iter()
+                .any(|e| e.category == ledger::Category::Events && e.amount == 100.0));
+        assert!(s.gold > gold);
+    }
+
+    #[test]
+    fn investments_never_lower_income() {
+        let mut m = manager(1);
+        m.automate = false;
+        let e = m.datafiles.events.iter().find(|e| e.name == "Investment").unwrap().clone();
+        m.activate_event(events::Scheduled { event: e, origin: None, after: None }).unwrap();
+        let boosts = sett(&m).boosts.resolve(&effects::Boosts::default());
+        assert_eq!(boosts.gold.mul, 1.0);
+        assert!(boosts.gold.add >= 50.0);
+        m.sett.as_mut().unwrap().collect_gold();
+        let s = sett(&m);
+        let tax = s.ledger.entries.iter().rev()
+            .find(|e| e.category == ledger::Category::Tax).unwrap().amount;
+        assert!((tax - 0.01 * s.pop).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dev_commands_need_dev_mode() {
+        let mut m = manager(1);
+        let (gold, pop) = (sett(&m).gold, sett(&m).pop);
+        let entries = m.hist.entries.len();
+        m.set(Some(String::from("gold")), Some(String::from("5000")), None, None);
+        m.set(Some(String::from("pop")), Some(String::from("5000")), None, None);
+        m.spawn(Some(String::from("hero")), None, None);
+        assert_eq!(sett(&m).gold, gold);
+        assert_eq!(sett(&m).pop, pop);
+        assert_eq!(m.hist.entries.len(), entries);
+    }
+
+    #[test]
+    fn items_are_only_spawned_where_they_can_be_kept() {
+        let mut m = manager(1);
+        // a new sett has no buildings to keep items in
+        assert!(enum_match!(m.spawn_item(None, None), Err(Error::CannotHold)));
+        let b = erect(&m, 0, "Library", 100.0);
+        let kind = m.datafiles.kinds.iter()
+            .find(|k| b.borrow().can_hold(&items::Item::new("Test", (*k).clone(), 0, 1.0)).is_ok())
+            .unwrap().name.clone();
+        m.spawn_item(Some(kind), Some(50)).unwrap();
+        assert_eq!(b.borrow().items.len(), 1);
+        assert!(logged(&m, "was spawned in the Library."));
+    }
+
+    #[test]
+    fn setting_gold_is_recorded_in_the_ledger() {
+        let mut m = manager(1);
+        m.toggle_dev();
+        let gold = sett(&m).gold;
+        m.set(Some(String::from("gold")), Some(String::from("5000")), None, None);
+        let s = sett(&m);
+        assert_eq!(s.gold, 5000.0);
+        let t = s.ledger.totals(s.age, Some(1));
+        let adjusted = t.categories.iter().find(|c| c.0 == ledger::Category::Adjustments)
+            .unwrap().1;
+        assert_eq!(adjusted, (5000.0 - gold, 0.0));
+        assert!(m.hist.entries.last().map(|e| e.author == history::Author::Dev).unwrap());
+    }
+}
@@ -1,55 +1,79 @@
 use prompts::Described;
+use items;
 use std::fmt;
 use std::default;
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use rand::Rng;
+
+// The lifespan against which a hero's chance of falling ill with age is
+// scaled, so that long-lived races do not sicken any sooner.
+const BASE_LIFESPAN: i32 = 10;
 
 #[derive(Debug, Serialize, Deserialize)]
 /// A hero of the settlement.
 pub struct Hero {
     pub name: String,
-    /// Once age reaches Race::max_age, the hero dies.
+    /// Once age reaches the lifespan of their race, the hero dies.
     pub age: i32,
     pub level: i32,
-    pub race: Race,
+    pub race: Rc<Race>,
     pub class: Rc<Class>,
     /// What the hero is currently doing.
     pub activity: Activity,
+    /// Items carried by the hero.
+    pub items: Vec<Rc<RefCell<items::Item>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A record of a hero who has died in the settlement.
+pub struct Grave {
+    pub hero: Rc<RefCell<Hero>>,
+    /// The step on which the hero died.
+    pub date: i32,
+    /// The cause of death.
+    pub cause: String,
+    /// Where the hero was based when they died.
+    pub place: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// The race of a person.
+/// Different races get different bonuses to their activities.
+/// Generated from lib/data/races.json
+pub struct Race {
+    pub name: String,
+    pub desc: String,
+    /// The maximum natural age of a person of the race, in units of
+    /// Hero::agemod() steps.
+    pub lifespan: i32,
+    /// Multiplier on the growth rate of quarters where the race is the
+    /// majority.
+    pub growth: f64,
+    /// Multipliers on the hero's activities, combined with their class's
+    /// activity boosts. Missing activities default to 1.0.
+    pub activities: BTreeMap<String, f64>,
+    /// Multipliers on the hero's contributions to the settlement
+    /// ("Growth", "Build" and "Gold"). Missing entries default to 1.0.
+    pub contributions: BTreeMap<String, f64>,
 }
 
-macro_attr! {
-    #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq,
-             IterVariants!(RaceVariants),
-             IterVariantNames!(RaceVariantNames),
-             EnumDisplay!, EnumFromStr!)]
-    /// The race of a person.
-    /// Different races get different bonuses to their activities.
-    pub enum Race {
-        /// Dwarves are hardy adventurers and strong builders.
-        Dwarf,
-        /// Elves are keen adventurers and good governors.
-        Elf,
-        /// Gnomes are hardy adventurers and fine traders.
-        Gnome,
-        /// Half elves are fertile and good governors.
-        Halfelf,
-        /// Halflings are fine traders and fertile.
-        Halfling,
-        /// Half orcs are strong builders and fertile.
-        Halforc,
-        /// Humans are fertile and hardy adventurers.
-        Human,
+impl fmt::Display for Race {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
-impl Race {
-    /// Return the maximum natural age of a person based on their race.
-    fn max_age(&self) -> i32 {
-        match self {
-            // Implemented for the case where someone might prefer
-            // that different races can be older than one another.
-            // TODO: make a game setting
-            _ => 10
+impl default::Default for Race {
+    fn default() -> Race {
+        Race {
+            name: String::from("DEFAULT"),
+            desc: String::from("defaultdesc"),
+            lifespan: BASE_LIFESPAN,
+            growth: 1.0,
+            activities: BTreeMap::new(),
+            contributions: BTreeMap::new(),
         }
     }
 }
@@ -65,14 +89,14 @@ pub struct Class {
     pub items: Vec<String>,
     pub age: i32,
     #[serde(rename = "activities")]
-    pub act_boosts: HashMap<String, f64>,
+    pub act_boosts: BTreeMap<String, f64>,
     pub powers: Vec<String>,
 }
 
 impl Class {
     fn new(n: &str, id: i32, d: &str,
            races: Vec<String>, bldgs: Vec<String>, items: Vec<String>,
-           age: i32, ab: HashMap<String, f64>, powers: Vec<String>) -> Class {
+           age: i32, ab: BTreeMap<String, f64>, powers: Vec<String>) -> Class {
         Class {
             name: n.to_string(),
             id: id,
@@ -95,7 +119,7 @@ impl fmt::Display for Class {
 
 impl default::Default for Class {
     fn default() -> Class {
-        let mut acts = HashMap::new();
+        let mut acts = BTreeMap::new();
         acts.insert(String::from("Working"), 1f64);
         acts.insert(String::from("Governing"), 1f64);
         acts.insert(String::from("Trading"), 0f64);
@@ -108,7 +132,7 @@ impl default::Default for Class {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum Activity {
     Working,
     Governing,
@@ -141,6 +165,21 @@ impl fmt::Display for Activity {
 }
 
 impl Activity {
+    /// Return the name used for the activity in the data files
+    /// (e.g. the keys of a class's activity boosts).
+    pub fn key(&self) -> &'static str {
+        match *self {
+            Activity::Working => "Working",
+            Activity::Governing => "Governing",
+            Activity::Trading(_) => "Trading",
+            Activity::Adventuring(_) => "Adventuring",
+            Activity::Resting(_) => "Resting",
+            Activity::Treasure(_) => "Treasure",
+            Activity::Dying(_) => "Dying",
+            Activity::Dead => "Dead",
+        }
+    }
+
     /// Return a String describing the cause of death based on the
     /// Activity last performed.
     ///
@@ -157,9 +196,9 @@ impl Activity {
             Activity::Governing => "under suspicious circumstances",
             Activity::Trading(_) => "on a trade expedition",
             Activity::Adventuring(_) => "in a deadly dungeon",
-            Activity::Resting(_) => "illness",
-            Activity::Treasure(_) => "greed",
-            _ => "old age",
+            Activity::Resting(_) => "of illness",
+            Activity::Treasure(_) => "of greed",
+            _ => "of old age",
         }.to_string()
     }
 }
@@ -188,17 +227,26 @@ impl fmt::Display for Hero {
     }
 }
 
+#[cfg(test)]
+impl Hero {
+    /// Return a new level 1 hero of the given name and class, of the
+    /// default race.
+    pub fn test(name: &str, class: Class) -> Rc<RefCell<Hero>> {
+        Rc::new(RefCell::new(Hero::new(name, 1, Rc::new(Race::default()), Rc::new(class))))
+    }
+}
+
 impl Hero {
     // Multiplier against level for determining time away
     // trading or adventuring
     // const AWAYMOD: i32 = 4;
     fn awaymod() -> i32 { 4 }
     // Multiplier against age for determining chance of resting or dying
-    // Heroes die naturally at AGEMOD * Race::max_age() steps.
+    // Heroes die naturally at AGEMOD * Race::lifespan steps.
     // const AGEMOD: i32 = 25;
     fn agemod() -> i32 { 25 }
 
-    pub fn new(n: &str, lvl: i32, race: Race, class: Rc<Class>) -> Hero {
+    pub fn new(n: &str, lvl: i32, race: Rc<Race>, class: Rc<Class>) -> Hero {
         Hero {
             name: n.to_string(),
             age: class.age * Hero::agemod(),
@@ -206,43 +254,100 @@ impl Hero {
             race: race,
             class: class,
             activity: Activity::Working,
+            items: vec!(),
+        }
+    }
+
+    /// Return the hero's multiplier for the given activity: their class's
+    /// activity boost times their race's trait. A multiplier of 0 means the
+    /// hero never takes up the activity.
+    pub fn act_boost(&self, act: &str) -> f64 {
+        self.class.act_boosts.get(act).cloned().unwrap_or(1.0)
+            * self.race.activities.get(act).cloned().unwrap_or(1.0)
+    }
+
+    /// Return the hero's contribution of the given kind ("Growth", "Build"
+    /// or "Gold") to their building and quarter this step, based on their
+    /// current activity, their activity boosts and their race.
+    /// Working and governing heroes contribute growth and build speed;
+    /// trading heroes also bring in gold. Adventuring, resting and dying
+    /// heroes do not contribute.
+    pub fn contribution(&self, kind: &str) -> f64 {
+        let base = match (&self.activity, kind) {
+            (&Activity::Working, "Growth") | (&Activity::Working, "Build") => 1.0,
+            (&Activity::Governing, "Growth") | (&Activity::Governing, "Build") => 1.0,
+            (&Activity::Trading(_), "Growth") | (&Activity::Trading(_), "Build")
+                | (&Activity::Trading(_), "Gold") => 1.0,
+            _ => 0.0,
+        };
+        base * self.act_boost(self.activity.key())
+            * self.race.contributions.get(kind).cloned().unwrap_or(1.0)
+    }
+
+    /// Strike the hero with a town effect that kills with the given chance
+    /// (between 0 and 1), giving the cause of death.
+    /// Heroes who are away or already dying are unaffected.
+    /// Return true if the hero was killed.
+    pub fn strike<R: Rng>(&mut self, chance: f64, cause: &str, rng: &mut R) -> bool {
+        if self.is_away() || self.is_dying() || rng.gen_range(0.0, 1.0) >= chance {
+            return false;
         }
+        self.activity = Activity::Dying(cause.to_string());
+        true
+    }
+
+    /// Return true if the hero is away from the sett (adventuring or trading).
+    /// Heroes who are away are immune to town effects.
+    pub fn is_away(&self) -> bool {
+        enum_match!(self.activity, Activity::Adventuring(_) | Activity::Trading(_))
     }
 
     /// Execute a timestep, aging the hero and changing their activity based on
     /// a provided percentile roll r (between 1 and 100, inclusive).
-    /// TODO: use new class format
+    /// The odds of each change are scaled by the hero's activity boosts, and
+    /// heroes with the DiseaseImmune power never fall ill.
     /// ```
     /// use podesta::people;
-    /// let h = people::Hero::new {
+    /// use std::rc::Rc;
+    /// let h = people::Hero::new(
     ///     "George",
     ///     1,
-    ///     people::Race::Human,
-    ///     people::Class::default()
-    /// };
+    ///     Rc::new(people::Race::default()),
+    ///     Rc::new(people::Class::default())
+    /// );
     /// assert_eq!(h.activity, people::Activity::Working);
     /// let mut r = 50;
     /// h.step(r);
     /// assert_eq!(h.activity, people::Activity::Adventuring);
     /// ```
     pub fn step(&mut self, r: i32) {
-        if self.age >= Hero::agemod() * self.race.max_age() {
-            self.activity = Activity::Dying("old age".to_string());
-            // TODO: Log the death
+        // the dying are left for the manager to bury
+        if self.is_dying() {
+            return;
+        }
+        let lifespan = self.race.lifespan.max(1);
+        if self.age >= Hero::agemod() * lifespan {
+            self.activity = Activity::Dying("of old age".to_string());
             // Don't need to do the rest so just return
             return;
         }
+        // scale a chance of misfortune down by a boost (ignoring zero boosts)
+        let ease = |chance: f64, boost: f64| if boost > 0.0 { chance / boost } else { chance };
+        let r = r as f64;
+        let aged = (self.age * BASE_LIFESPAN / (Hero::agemod() * lifespan)) as f64;
+        let immune = self.class.powers.contains(&"DiseaseImmune".to_string());
         let next = match self.activity {
             Activity::Working => {
-                if r < 2 {
+                let trade = self.act_boost("Trading");
+                let adv = self.act_boost("Adventuring");
+                if r < 2.0 {
                     Activity::Dying(self.activity.autopsy())
-                } else if r < (self.age / Hero::agemod() + 4) {
+                } else if r < aged + 4.0 {
                     // TODO: replace hard-coded numbers
-                    Activity::Resting(10)
-                } else if r < (self.age / Hero::agemod() + 29) {
+                    if immune { Activity::Working } else { Activity::Resting(10) }
+                } else if r < aged + 4.0 + 25.0 * trade.max(adv) {
                     let away = Hero::awaymod() * self.level;
-                    //TODO: allow for possible hybrids
-                    if self.class.powers.contains(&"Trading".to_string()) {
+                    if trade > adv {
                         Activity::Trading(away)
                     } else {
                         Activity::Adventuring(away)
@@ -250,25 +355,25 @@ impl Hero {
                 } else {
                     Activity::Working
                 }
-                // Manager:
-                // Growth and build speed increase
+                // Quarter:
+                // Growth and build speed increase (see contribution)
             },
             Activity::Governing => {
-                if r < 3 {
+                if r < ease(3.0, self.act_boost("Governing")) {
                     Activity::Dying(self.activity.autopsy())
-                } else if r < (self.age / Hero::agemod() + 5) {
+                } else if !immune && r < aged + 5.0 {
                     Activity::Resting(15) }
                 else {
                     Activity::Governing
                 }
-                // Manager:
-                // Growth and build speed increase
+                // Quarter:
+                // Growth and build speed increase (see contribution)
             },
             Activity::Trading(steps) => {
                 if steps > 0 {
-                    if r < 3 {
+                    if r < ease(3.0, self.act_boost("Trading")) {
                         Activity::Dying(self.activity.autopsy())
-                    } else if r < (self.age / Hero::agemod() + 5) {
+                    } else if !immune && r < aged + 5.0 {
                         Activity::Resting(15)
                     } else {
                         Activity::Trading(steps - 1)
@@ -276,15 +381,15 @@ impl Hero {
                 } else {
                     Activity::Working
                 }
-                // Manager:
-                // Growth, build and gold increase
-                // Immune to town effects
+                // Quarter:
+                // Growth, build and gold increase (see contribution)
+                // Immune to town effects (see is_away)
             },
             Activity::Adventuring(steps) => {
                 if steps > 0 {
-                    if r < 6 {
+                    if r < ease(6.0, self.act_boost("Adventuring")) {
                         Activity::Dying(self.activity.autopsy())
-                    } else if r < (self.age / Hero::agemod() + 8) {
+                    } else if !immune && r < aged + 8.0 {
                         Activity::Resting(20)
                     } else {
                         Activity::Adventuring(steps - 1)
@@ -293,43 +398,186 @@ impl Hero {
                     self.level += 1;
                     Activity::Treasure(self.level)
                 }
-                // Manager:
-                // Immune to town effects
+                // Immune to town effects (see is_away)
             },
             Activity::Resting(steps) => {
-                if steps == 0 || r > 74 {
+                let rest = self.act_boost("Resting");
+                if steps == 0 || r > 100.0 - 26.0 * rest {
                     Activity::Working
-                } else if r < steps {
+                } else if r < ease(steps as f64, rest) {
                     Activity::Dying(self.activity.autopsy())
                 } else {
                     Activity::Resting(steps - 1)
                 }
             },
-            Activity::Treasure(_) => Activity::Working, //TODO: add effect
+            Activity::Treasure(_) => Activity::Working,
+            // Manager:
+            // Gold (and possibly an item) is collected on entering Treasure
             _ => Activity::Dead,
         };
         self.activity = next;
         self.age += 1;
     }
 
-    #[allow(dead_code)]
     /// Promote a hero to the Governing activity.
     ///
     /// # Examples
     ///
     /// ```
     /// use podesta::people;
+    /// use std::rc::Rc;
     /// let h = people::Hero::new(
     ///     "George",
     ///     1,
-    ///     people::Race::Human,
-    ///     people::Class::Fighter
+    ///     Rc::new(people::Race::default()),
+    ///     Rc::new(people::Class::default())
     /// );
     /// assert_eq!(h.activity, people::Activity::Working);
     /// h.make_governor();
     /// assert_eq!(h.activity, people::Activity::Governing);
     /// ```
-    fn make_governor(&mut self) {
+    pub fn make_governor(&mut self) {
         self.activity = Activity::Governing;
     }
+
+    /// Return true if the hero is currently dying.
+    pub fn is_dying(&self) -> bool {
+        enum_match!(self.activity, Activity::Dying(_))
+    }
+
+    /// Return true if the hero is currently governing.
+    pub fn is_governing(&self) -> bool {
+        self.activity == Activity::Governing
+    }
+}
+
+impl Grave {
+    /// Record the death of the given hero on the given date, marking them
+    /// as dead. The cause of death is taken from the hero's last activity.
+    pub fn new(hero: Rc<RefCell<Hero>>, date: i32, place: &str) -> Grave {
+        let cause = match hero.borrow().activity {
+            Activity::Dying(ref c) => c.clone(),
+            ref a => a.autopsy(),
+        };
+        hero.borrow_mut().activity = Activity::Dead;
+        Grave {
+            hero,
+            date,
+            cause,
+            place: place.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Grave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let h = self.hero.borrow();
+        write!(f, "{}, a level {} {} {} of {} - died {} at step {}, aged {}",
+               h.name, h.level, h.race, h.class, self.place, self.cause, self.date, h.age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(acts: &[(&str, f64)], powers: &[&str]) -> Rc<Class> {
+        let mut c = Class::default();
+        for &(a, x) in acts {
+            c.act_boosts.insert(a.to_string(), x);
+        }
+        c.powers = powers.iter().map(|p| p.to_string()).collect();
+        Rc::new(c)
+    }
+
+    fn race(acts: &[(&str, f64)], contribs: &[(&str, f64)]) -> Rc<Race> {
+        Rc::new(Race {
+            activities: acts.iter().map(|&(a, x)| (a.to_string(), x)).collect(),
+            contributions: contribs.iter().map(|&(a, x)| (a.to_string(), x)).collect(),
+            ..Race::default()
+        })
+    }
+
+    #[test]
+    fn activity_boosts_combine_class_and_race() {
+        let h = Hero::new("Ann", 1, race(&[("Governing", 1.25)], &[]),
+                          class(&[("Governing", 1.2)], &[]));
+        assert!((h.act_boost("Governing") - 1.5).abs() < 1e-12);
+        // missing entries count as 1
+        assert_eq!(h.act_boost("Adventuring"), 1.0);
+    }
+
+    #[test]
+    fn boosts_choose_between_trading_and_adventuring() {
+        let mut trader = Hero::new("Ann", 2, Rc::new(Race::default()),
+                                   class(&[("Trading", 2.0)], &[]));
+        trader.step(10);
+        assert_eq!(trader.activity, Activity::Trading(8));
+        let mut adventurer = Hero::new("Bob", 2, Rc::new(Race::default()),
+                                       class(&[("Trading", 0.0)], &[]));
+        adventurer.step(10);
+        assert_eq!(adventurer.activity, Activity::Adventuring(8));
+    }
+
+    #[test]
+    fn disease_immune_heroes_never_rest() {
+        let mut h = Hero::new("Ann", 1, Rc::new(Race::default()), class(&[], &[]));
+        h.step(3);
+        assert_eq!(h.activity, Activity::Resting(10));
+        let mut immune = Hero::new("Bob", 1, Rc::new(Race::default()),
+                                   class(&[], &["DiseaseImmune"]));
+        immune.step(3);
+        assert_eq!(immune.activity, Activity::Working);
+    }
+
+    #[test]
+    fn contributions_depend_on_activity() {
+        let mut h = Hero::new("Ann", 1, Rc::new(Race::default()), class(&[("Trading", 1.0)], &[]));
+        assert_eq!(h.contribution("Growth"), 1.0);
+        assert_eq!(h.contribution("Gold"), 0.0);
+        h.activity = Activity::Trading(3);
+        assert_eq!(h.contribution("Gold"), 1.0);
+        h.activity = Activity::Adventuring(3);
+        assert_eq!(h.contribution("Build"), 0.0);
+        h.activity = Activity::Resting(3);
+        assert_eq!(h.contribution("Growth"), 0.0);
+    }
+
+    #[test]
+    fn race_traits_scale_contributions() {
+        let h = Hero::new("Ann", 1, race(&[], &[("Build", 1.25)]), class(&[], &[]));
+        assert_eq!(h.contribution("Build"), 1.25);
+        assert_eq!(h.contribution("Growth"), 1.0);
+    }
+
+    fn aged(lifespan: i32) -> Hero {
+        Hero::new("Ann", 1, Rc::new(Race { lifespan, ..Race::default() }),
+                  class(&[], &[]))
+    }
+
+    #[test]
+    fn heroes_die_of_old_age_at_their_race_lifespan() {
+        let mut h = aged(10);
+        h.age = Hero::agemod() * 10 - 1;
+        h.step(100);
+        assert_eq!(h.activity, Activity::Working);
+        h.step(100);
+        assert_eq!(h.activity, Activity::Dying(String::from("of old age")));
+    }
+
+    #[test]
+    fn long_lived_races_outlive_short_lived_ones() {
+        let mut elf = aged(35);
+        let mut humans = 0;
+        let mut human = aged(10);
+        while !elf.is_dying() {
+            elf.step(100);
+            human.step(100);
+            if human.is_dying() {
+                humans += 1;
+                human = aged(10);
+            }
+        }
+        assert_eq!(humans, 3);
+    }
 }
@@ -3,7 +3,7 @@
 use std::io::{self, Write};
 use std::cmp;
 use std::num;
-use rand::{self, Rng};
+use rand::Rng;
 use std::fmt;
 use std::error;
 use std::rc::Rc;
@@ -94,6 +94,19 @@ pub fn name_file(phrase: &str) -> Result<String, PromptError> {
     }.and_then(|x| Ok(x.to_string()))
 }
 
+/// Prompt the user to type a line of text after the given question.
+/// Return Ok(text), which may be empty, if it was successfully read,
+/// otherwise return Err(PromptError).
+pub fn text(question: &str) -> Result<String, PromptError> {
+    print!("{}", question);
+    io::stdout().flush().expect("Failed to flush to stdout!");
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input) {
+        Ok(_) => Ok(input.trim().to_string()),
+        Err(e) => Err(PromptError::Io(e)),
+    }
+}
+
 /// Prompt the user with a boolean choice, with a given question,
 /// expected affirmative answers (returning Ok(true)) and
 /// expected negative answers (returning Ok(false)).
@@ -119,9 +132,9 @@ pub fn bool_choose(question: &str, aff: &[&str], neg: &[&str])
 }
 
 /// Re-prompt the user with a boolean choice a maximum number of times
-/// or randomly choose one of the two options.
-pub fn bool_choose_or_rand(question: &str, aff: &[&str], neg: &[&str],
-                           maxprompts: i32) -> bool {
+/// or randomly choose one of the two options using the given generator.
+pub fn bool_choose_or_rand<R: Rng>(question: &str, aff: &[&str], neg: &[&str],
+                                   maxprompts: i32, rng: &mut R) -> bool {
     let mut numprompts = 0;
     while numprompts < maxprompts {
         match bool_choose(question, aff, neg) {
@@ -132,7 +145,7 @@ pub fn bool_choose_or_rand(question: &str, aff: &[&str], neg: &[&str],
         numprompts += 1;
     }
     // coin flip
-    rand::thread_rng().gen_range(0, 2) == 0
+    rng.gen_range(0, 2) == 0
 }
 
 /// Prompt the user for a choice from the given list of displayable items.
@@ -161,8 +174,10 @@ pub fn choose<T: fmt::Display>(a: &[T]) -> Result<usize, PromptError> {
 
 /// Prompt the user for a choice from the given list of displayable items,
 /// and return the index of the chosen item. If the user fails to make a
-/// choice maxprompts times, pick a random item index.
-pub fn choose_or_rand<T: fmt::Display>(a: &[T], maxprompts: i32) -> usize {
+/// choice maxprompts times, pick a random item index using the given generator.
+pub fn choose_or_rand<T, R>(a: &[T], maxprompts: i32, rng: &mut R) -> usize
+    where T: fmt::Display, R: Rng
+{
     let mut numprompts = 0;
     while numprompts < maxprompts {
         match choose(a) {
@@ -172,7 +187,7 @@ pub fn choose_or_rand<T: fmt::Display>(a: &[T], maxprompts: i32) -> usize {
         }
         numprompts += 1;
     }
-    rand::thread_rng().gen_range(0, a.len())
+    rng.gen_range(0, a.len())
 }
 
 /// Prompt the user for a choice from the given list of displayable items,
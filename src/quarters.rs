@@ -1,11 +1,13 @@
 use buildings;
 use people;
 use effects;
+use ledger;
 use prompts;
 use std::fmt;
 use std::error;
 use std::cell::RefCell;
 use std::rc::Rc;
+use rand::Rng;
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,19 +15,21 @@ pub struct Quarter {
     /// The unique name of the quarter.
     pub name: String,
     /// The quarter's "type" (what kind of activities take place here).
-    pub qtype: QType,
+    pub qtype: Rc<QuarterType>,
     /// The total population of the quarter.
     pub pop: f64,  // Stored as float for added precision, displayed as int
     /// The total age in steps of the quarter.
     pub age: i32,
     /// The majority race of the quarter.
-    pub race: people::Race,
+    pub race: Rc<people::Race>,
     /// The buildings constructed in the quarter.
     pub bldgs: Vec<Rc<RefCell<buildings::Building>>>,
     /// The growth rate of the quarter's population.
     pub growth: f64,
     /// The possible effect bonuses in the quarter.
     pub boosts: effects::EffectFlags,
+    /// The population lost to disasters, which is slowly regrown.
+    pub losses: f64,
 }
 
 impl prompts::Described for Quarter {
@@ -50,40 +54,36 @@ impl fmt::Display for Quarter {
     }
 }
 
-macro_attr! {
-    #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq,
-             IterVariants!(QTypeVariants),
-             IterVariantNames!(QTypeVariantNames),
-             EnumDisplay!, EnumFromStr!)]
-    /// The focus of the quarter.
-    pub enum QType {
-        /// Mostly residences
-        Residential,
-        /// Mostly artisans and shops
-        Industrial,
-        /// Waterfront buildings (only available in coastal settlements)
-        Port,
-        /// Educational institutions
-        Academic,
-        /// Military and government buildings
-        Administrative,
-    }
+#[derive(Serialize, Deserialize, Debug)]
+/// The focus of a quarter (what kind of activities take place there).
+/// Generated from lib/data/quarters.json
+pub struct QuarterType {
+    pub name: String,
+    pub desc: String,
+    /// The growth rate of the population of quarters of this type.
+    pub growth: f64,
+    /// Whether the type is only available in coastal settlements.
+    pub coastal: bool,
+    /// The coefficient of each building of this type (see
+    /// BuildingPlan::btype) in the regression deciding whether a new
+    /// quarter is founded.
+    pub founding: f64,
+    /// The types of building (see BuildingPlan::btype) that can be built in
+    /// quarters of this type.
+    pub tags: Vec<String>,
 }
 
-impl QType {
-    /// Return a vec of QTypes, filtering out the Port QType if
-    /// is_coastal is false.
-    pub fn get_qtypes(is_coastal: bool) -> Vec<Self> {
-        Self::iter_variants().filter(|&q| is_coastal || q != QType::Port)
-            .collect::<Vec<_>>()
+impl fmt::Display for QuarterType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
+}
 
-    /// Return a vec of QType strings, filtering out the Port QType if
-    /// is_coastal is false.
-    pub fn get_qtype_names(is_coastal: bool) -> Vec<String> {
-        Self::iter_variant_names()
-            .filter(|&q| is_coastal || q != format!("{}", QType::Port))
-            .map(|x| x.to_string()).collect::<Vec<_>>()
+impl QuarterType {
+    /// Return true if buildings of the given plan can be built in quarters
+    /// of this type.
+    pub fn allows(&self, plan: &buildings::BuildingPlan) -> bool {
+        self.tags.contains(&plan.btype)
     }
 }
 
@@ -100,12 +100,20 @@ pub enum BuildError {
     NotEnoughGold,
     /// A building of that name already exists
     AlreadyExists,
-    /// Port quarter can't be constructed inland
+    /// Coastal quarter can't be constructed inland
     InlandPort,
     /// Prerequisite buildings missing
     PrereqsMissing,
     /// No building found for given repairs
     NoBuildingFound,
+    /// Building is still under construction
+    UnderConstruction,
+    /// Building is already in full repair
+    NothingToRepair,
+    /// Building is not in ruins
+    NotRuined,
+    /// Building cannot be used as asked (see buildings::OccupyError)
+    Occupy(buildings::OccupyError),
 }
 impl fmt::Display for BuildError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -121,11 +129,18 @@ impl fmt::Display for BuildError {
             BuildError::AlreadyExists =>
                 write!(f, "A structure by that name already exists"),
             BuildError::InlandPort =>
-                write!(f, "Cannot add a port quarter to an inland sett"),
+                write!(f, "Cannot add a coastal quarter to an inland sett"),
             BuildError::PrereqsMissing =>
                 write!(f, "Prerequisite buildings not yet constructed"),
             BuildError::NoBuildingFound =>
                 write!(f, "No buildings of the given name found"),
+            BuildError::UnderConstruction =>
+                write!(f, "Building is still under construction"),
+            BuildError::NothingToRepair =>
+                write!(f, "Building is already in full repair"),
+            BuildError::NotRuined =>
+                write!(f, "Building is not in ruins"),
+            BuildError::Occupy(ref e) => e.fmt(f),
         }
     }
 }
@@ -141,26 +156,42 @@ impl error::Error for BuildError {
             BuildError::InlandPort => "inland port",
             BuildError::PrereqsMissing => "prereqs missing",
             BuildError::NoBuildingFound => "no valid building found",
+            BuildError::UnderConstruction => "building under construction",
+            BuildError::NothingToRepair => "nothing to repair",
+            BuildError::NotRuined => "building not ruined",
+            BuildError::Occupy(_) => "building cannot be used",
         }
     }
 
     fn cause(&self) -> Option<&error::Error> { None }
 }
 
+impl From<buildings::OccupyError> for BuildError {
+    fn from(err: buildings::OccupyError) -> BuildError {
+        match err {
+            // repairs need a building that is no longer under construction
+            buildings::OccupyError::NotInUse => BuildError::UnderConstruction,
+            buildings::OccupyError::NotRuined => BuildError::NotRuined,
+            e => BuildError::Occupy(e),
+        }
+    }
+}
+
 
 impl Quarter {
     /// Create a new Quarter with a given name, population, type and racial
     /// majority. Age is set to zero and no buildings exist initially.
-    pub fn new(n: &str, qt: QType, p: f64, r: people::Race) -> Quarter {
+    pub fn new(n: &str, qt: Rc<QuarterType>, p: f64, r: Rc<people::Race>) -> Quarter {
         Quarter {
             name: n.to_string(),
+            growth: qt.growth * r.growth,
             qtype: qt,
             pop: p,
             age: 0,
             race: r,
             bldgs: vec!(),
-            growth: 0.01,
             boosts: effects::EffectFlags::default(),
+            losses: 0.0,
         }
     }
 
@@ -176,22 +207,52 @@ impl Quarter {
     /// `start`: the base starting population of the quarter = 50
     /// `reg_growth`: the growth modifier of the sett's region
     /// `r`: the growth rate of the quarter
+    ///
+    /// Any population lost to disasters is subtracted from P(t) and regrown
+    /// at the quarter's growth rate.
     /// [logif]: https://en.wikipedia.org/wiki/Logistic_function
-    pub fn step(&mut self, reg_growth: f64) {
+    ///
+    /// The quarter's effects are resolved on top of the given boosts of its
+    /// sett, and passed on to its buildings.
+    /// Return the name of each building which fell into ruin, with the
+    /// occupants it drove out (see Building::step).
+    pub fn step<R: Rng>(&mut self, reg_growth: f64, parent: &effects::Boosts, rng: &mut R)
+        -> Vec<(String, Vec<Rc<RefCell<people::Hero>>>)>
+    {
         self.age += 1;
+        // heroes at work in the quarter's buildings boost its growth
+        let grow = self.bldgs.iter()
+            .fold(0.0, |acc, b| acc + b.borrow_mut().add_contributions());
+        self.boosts.add(effects::Modifier::new("Heroes at work", "occupants",
+                                               effects::Target::Grow, effects::Combine::Mul,
+                                               1.0 + grow, 1));
+        let boosts = self.boosts.resolve(parent);
         let grow_rate = |r: f64, t: f64| -> f64 { (r * t).exp() };
         // simplify the constants
         let e_rt : f64 = grow_rate(self.growth, self.age as f64);
         // add bonus based on the difference between the old pop and the new
-        let newpop = 5000000.0 * reg_growth * e_rt / (99950.0 + 50.0 * e_rt);
-        self.pop = newpop + self.boosts.grow.next().unwrap_or(1.0) * (newpop - self.pop).abs();
+        let newpop = (5000000.0 * reg_growth * e_rt / (99950.0 + 50.0 * e_rt)
+                      - self.losses).max(0.0);
+        // losses are regrown at the quarter's growth rate
+        self.losses *= 1.0 - self.growth;
+        self.pop = newpop + boosts.grow.apply((newpop - self.pop).abs());
+        let mut ruined = vec![];
         for bldg in self.bldgs.iter() {
-            // include a boost or bonus for buildings
-            // FIXME: this screws with the building's boosts by continually recloning the build
-            // FIXME: and multiplying it over and over on top of itself.
-            bldg.borrow_mut().boosts.build *= self.boosts.build.clone();
-            bldg.borrow_mut().step();
+            let mut b = bldg.borrow_mut();
+            if let Some(evicted) = b.step(&boosts, rng) {
+                ruined.push((b.name.clone(), evicted));
+            }
         }
+        ruined
+    }
+
+    /// Kill the given fraction (between 0 and 1) of the quarter's population.
+    /// Return the number of people killed.
+    pub fn kill(&mut self, frac: f64) -> f64 {
+        let dead = (self.pop * frac.min(1.0)).round();
+        self.pop -= dead;
+        self.losses += dead;
+        dead
     }
 
     /// Add a building using the given BuildingPlan plan.
@@ -209,14 +270,30 @@ impl Quarter {
         Ok(())
     }
 
-    /// Collect gold. For each occupant in a building, collect an extra 0.04
-    /// gold times the optional boost.
-    pub fn collect_gold(&mut self) -> f64 {
-        let boost : f64 = self.boosts.gold.next().unwrap_or(1.0);
-        self.bldgs.iter().fold(0.0, |acc, b| acc + b.borrow_mut().collect_gold() * boost)
+    /// Collect gold from each building in the quarter, resolving the
+    /// quarter's effects on top of the given boosts of its sett.
+    /// Return the gold collected in each ledger category.
+    pub fn collect_gold(&self, parent: &effects::Boosts) -> Vec<(ledger::Category, f64)> {
+        let boosts = self.boosts.resolve(parent);
+        let (bldgs, items) = self.bldgs.iter().fold((0.0, 0.0), |acc, b| {
+            let (bldg, item) = b.borrow().collect_gold(&boosts);
+            (acc.0 + bldg, acc.1 + item)
+        });
+        vec![(ledger::Category::Buildings, bldgs),
+             (ledger::Category::Items, items),
+             (ledger::Category::Events, boosts.gold.add)]
     }
 
 
+    /// Remove the building with the given name from the quarter.
+    /// Return the building if it was found.
+    pub fn remove_building(&mut self, bname: &str)
+        -> Option<Rc<RefCell<buildings::Building>>>
+    {
+        self.bldgs.iter().position(|b| b.borrow().name == bname)
+            .map(|i| self.bldgs.remove(i))
+    }
+
     /// Find a building based on a given name.
     /// Return Some(Rc<RefCell<building>) if one is found,
     /// otherwise None.
@@ -226,3 +303,89 @@ impl Quarter {
         self.bldgs.iter().find(|ref b| b.borrow().name == bname).map(|b| b.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use items;
+
+    fn quarter() -> Quarter {
+        let qtype = Rc::new(QuarterType {
+            name: String::from("Trade"),
+            desc: String::new(),
+            growth: 0.01,
+            coastal: false,
+            founding: 0.0,
+            tags: vec![String::from("Trade")],
+        });
+        Quarter::new("Market", qtype, 50.0, Rc::new(people::Race::default()))
+    }
+
+    /// Add a building in use to the quarter, holding an item worth 10 gold
+    /// a step.
+    fn stock(q: &mut Quarter) {
+        q.add_building(Rc::new(buildings::BuildingPlan {
+            btype: String::from("Trade"),
+            ..buildings::BuildingPlan::test("Warehouse")
+        })).unwrap();
+        let kind = Rc::new(items::ItemKind {
+            name: String::from("Trinket"),
+            desc: String::new(),
+            buildings: vec![String::from("ALL")],
+            magic: 0.0,
+            max_power: 1,
+            income: 0.1,
+        });
+        let b = q.find_building("Warehouse").unwrap();
+        b.borrow_mut().cond = buildings::BldgCond::InUse(100.0);
+        b.borrow_mut().add_item(Rc::new(RefCell::new(items::Item::new("Vase", kind, 1, 100.0))))
+            .unwrap();
+    }
+
+    fn collected(q: &Quarter, parent: &effects::Boosts, cat: ledger::Category) -> f64 {
+        q.collect_gold(parent).into_iter().filter(|&(c, _)| c == cat).map(|(_, g)| g).sum()
+    }
+
+    #[test]
+    fn sett_multipliers_apply_to_items_once() {
+        let mut q = quarter();
+        stock(&mut q);
+        let none = effects::Boosts::default();
+        assert!((collected(&q, &none, ledger::Category::Items) - 10.0).abs() < 1e-9);
+        let mut sett = effects::EffectFlags::default();
+        sett.add(effects::Modifier::new("Strike", "the test", effects::Target::Gold,
+                                        effects::Combine::Mul, 0.5, 1));
+        let halved = sett.resolve(&none);
+        assert!((collected(&q, &halved, ledger::Category::Items) - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quarter_bonuses_are_not_paid_by_its_buildings() {
+        let mut q = quarter();
+        stock(&mut q);
+        q.boosts.add(effects::Modifier::new("Festival", "the test", effects::Target::Gold,
+                                            effects::Combine::Add, 7.0, 1));
+        let none = effects::Boosts::default();
+        assert_eq!(collected(&q, &none, ledger::Category::Events), 7.0);
+        assert_eq!(collected(&q, &none, ledger::Category::Buildings), 0.0);
+    }
+
+    #[test]
+    fn occupy_errors_map_to_their_build_errors() {
+        use buildings::OccupyError;
+        assert!(enum_match!(BuildError::from(OccupyError::NotInUse),
+                            BuildError::UnderConstruction));
+        assert!(enum_match!(BuildError::from(OccupyError::NotRuined), BuildError::NotRuined));
+        assert!(enum_match!(BuildError::from(OccupyError::Full),
+                            BuildError::Occupy(OccupyError::Full)));
+    }
+
+    #[test]
+    fn majority_race_scales_growth() {
+        let q = quarter();
+        let fertile = Rc::new(people::Race { growth: 1.5, ..people::Race::default() });
+        let q2 = Quarter::new("Market", q.qtype.clone(), 50.0, fertile);
+        assert!((q.growth - 0.01).abs() < 1e-12);
+        assert!((q2.growth - 0.015).abs() < 1e-12);
+    }
+}
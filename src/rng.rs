@@ -0,0 +1,56 @@
+//! The random number generator used by the simulation.
+//! Every random decision made while running a settlement draws from a single
+//! SimRng owned by the manager, so that the same seed and the same commands
+//! always produce the same settlement.
+
+use rand::{self, Rng};
+
+/// A small, serializable generator (SplitMix64) that remembers the seed it
+/// was started from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimRng {
+    /// The seed the generator was last (re)started from.
+    seed: u64,
+    /// The current internal state.
+    state: u64,
+}
+
+impl SimRng {
+    /// Create a new SimRng starting from the given seed.
+    pub fn new(seed: u64) -> SimRng {
+        SimRng {
+            seed,
+            state: seed,
+        }
+    }
+
+    /// Create a new SimRng with a seed drawn from the thread's generator.
+    pub fn from_entropy() -> SimRng {
+        SimRng::new(rand::thread_rng().gen())
+    }
+
+    /// Return the seed the generator was started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restart the generator from the given seed.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.state = seed;
+    }
+}
+
+impl Rng for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
@@ -2,14 +2,45 @@
 //! the basic object which represents the growing settlement.
 use quarters;
 use buildings;
+use items;
 use regions;
 use people;
 use events;
 use effects;
+use ledger;
 use prompts::Described;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 use std::fmt;
+use rand::Rng;
+
+// Coefficients of the logistic regression deciding whether a new quarter is
+// founded on a given step (see docs/quarters.txt).
+// The intercept grows by FOUND_POP for every nextqrtr people in the sett.
+const FOUND_BASE: f64 = -8.0;
+const FOUND_POP: f64 = 0.1;
+const FOUND_AGE: f64 = 0.01;
+
+// Number of steps kept in the ledger.
+const LEDGER_STEPS: i32 = 50;
+
+// An item left by a dead hero which no building could hold, with the name
+// of the hero who carried it.
+type LostItem = (String, Rc<RefCell<items::Item>>);
+
+macro_attr! {
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq,
+         IterVariants!(SeasonVariants),
+         EnumDisplay!)]
+    /// A season of the year. Each step of the settlement lasts one season.
+    pub enum Season {
+        Spring,
+        Summer,
+        Autumn,
+        Winter,
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Sett {
@@ -17,24 +48,38 @@ pub struct Sett {
     pub age: i32,
     pub pop: f64,  // use a float for more precise updating (display as an int)
     pub gold: f64,
+    /// Gold paid for the upkeep of buildings on the last step.
+    pub upkeep: f64,
+    /// Income and spending over the last few steps.
+    pub ledger: ledger::Ledger,
     pub reg: Rc<regions::Region>,
     /// List of quarters in the settlement.
     pub qrtrs: Vec<Rc<RefCell<quarters::Quarter>>>,
-    /// Turns before a new quarter is added.
+    /// Population per unit of the new quarter regression's intercept.
+    /// Doubles each time a quarter is added.
     pub nextqrtr: i32,
+    /// Heroes living in the settlement without a headquarters building,
+    /// such as its first governor.
+    pub visitors: Vec<Rc<RefCell<people::Hero>>>,
+    /// Heroes who have died in the settlement.
+    pub graveyard: Vec<people::Grave>,
     /// Flag for if settlement is coastal.
     pub coastal: bool,
     //flag for growth/build/gold
     pub boosts: effects::EffectFlags,
+    /// The step on which each event last fired, by event name.
+    pub fired: BTreeMap<String, i32>,
 }
 
 impl Sett {
-    /// Create a new Settlement
+    /// Create a new Settlement.
+    /// If a hero is given, they are promoted to be its first governor.
     pub fn new(n: String,
                reg: Rc<regions::Region>,
-               qt: quarters::QType,
-               r: people::Race,
+               qt: Rc<quarters::QuarterType>,
+               r: Rc<people::Race>,
                coast: bool,
+               gov: Option<people::Hero>,
     ) -> Sett {
         // get the starting population based on the region's growth
         let pop = 50.0 * reg.growth;
@@ -43,37 +88,94 @@ impl Sett {
             age: 0,
             pop: pop,
             gold: reg.starting_gold,
+            upkeep: 0.0,
+            ledger: ledger::Ledger::new(LEDGER_STEPS),
             reg: reg,
             qrtrs: vec![Rc::new(
                 RefCell::new(quarters::Quarter::new("Main", qt, pop, r)))],
-            // TODO: get a starting governor and governing hall?
             nextqrtr: 50,
+            visitors: gov.into_iter().map(|mut h| {
+                h.make_governor();
+                Rc::new(RefCell::new(h))
+            }).collect(),
+            graveyard: vec![],
             coastal: coast,
             boosts: effects::EffectFlags::default(),
+            fired: BTreeMap::new(),
         }
     }
 
-    /// Execute settlement timestep and perform the following actions:
+    /// Execute settlement timestep, drawing all random rolls from the given
+    /// generator, and perform the following actions:
     /// - Increment settlement age by 1.
     /// - Perform quarters::step() for each quarter, and
-    /// - calculate new population from the quarters.
+    /// - calculate new population from the quarters (whose growth already
+    ///   includes the sett's growth boosts).
     /// - Increment gold for the settlement.
-    /// - Compute an event map for the step and return it.
-    pub fn step(&mut self) -> events::EventMap {
+    /// - Compute an event map for the step and return it, noting whether
+    ///   a new quarter should be founded (weighing the buildings by the given
+    ///   quarter types) and which buildings fell into ruin.
+    pub fn step<R: Rng>(&mut self, qtypes: &[Rc<quarters::QuarterType>], rng: &mut R)
+        -> events::EventMap
+    {
         // new population (sum of quarters' population)
         let mut newpop = 0f64;
         self.age += 1;
+        // heroes without a headquarters (e.g. the governor) contribute to
+        // every quarter
+        let (grow, build) = self.visitors.iter().fold((0.0, 0.0), |(gw, bu), h| {
+            let h = h.borrow();
+            (gw + h.contribution("Growth"), bu + h.contribution("Build"))
+        });
+        self.boosts.add(effects::Modifier::new("Heroes at work", "visitors",
+                                               effects::Target::Grow, effects::Combine::Mul,
+                                               1.0 + buildings::HERO_GROW * grow, 1));
+        self.boosts.add(effects::Modifier::new("Heroes at work", "visitors",
+                                               effects::Target::Build, effects::Combine::Mul,
+                                               1.0 + buildings::HERO_BUILD * build, 1));
+        let boosts = self.boosts.resolve(&effects::Boosts::default());
         // call each quarter's step
+        let mut ruined = vec![];
         for q in &self.qrtrs {
-            q.borrow_mut().step(self.reg.growth);
-            newpop += q.borrow().pop;
+            let mut q = q.borrow_mut();
+            for (building, evicted) in q.step(self.reg.growth, &boosts, rng) {
+                ruined.push((events::Origin { quarter: q.name.clone(), building }, evicted));
+            }
+            newpop += q.pop;
+        }
+        for hero in &self.visitors {
+            hero.borrow_mut().step(rng.gen_range(1, 101));
         }
-        // include the growth bonus based on diff in pop
-        self.pop = newpop + self.boosts.grow.next().unwrap_or(1.0) * (newpop - self.pop).abs();
-        // accumulate gold (TODO: replace None with boost option)
+        self.pop = newpop;
+        // accumulate gold
         self.collect_gold();
+        // count down every effect, now that they have been applied
+        self.tick_effects();
         // compute event chances and return an eventmap
-        self.compute_events()
+        let mut map = self.compute_events();
+        map.founding = rng.gen_range(0f64, 1f64) < self.founding_chance(qtypes);
+        map.ruined = ruined;
+        map
+    }
+
+    /// Return the probability that a new quarter is founded this step,
+    /// using a logistic regression on the sett's population, its age and
+    /// the number of buildings of each type, weighted by the coefficient of
+    /// the given quarter type of the same name.
+    pub fn founding_chance(&self, qtypes: &[Rc<quarters::QuarterType>]) -> f64 {
+        let mut x = FOUND_BASE + FOUND_POP * self.pop / self.nextqrtr as f64
+            + FOUND_AGE * self.age as f64;
+        for (_, b) in self.get_buildings() {
+            let b = b.borrow();
+            x += qtypes.iter().find(|qt| qt.name == b.plan.btype)
+                .map(|qt| qt.founding).unwrap_or(0.0);
+        }
+        1.0 / (1.0 + (-x).exp())
+    }
+
+    /// Return the current season.
+    pub fn season(&self) -> Season {
+        Season::iter_variants().nth(self.age as usize % 4).unwrap_or(Season::Spring)
     }
 
     /// Compute the event chances for this step.
@@ -81,13 +183,15 @@ impl Sett {
         let mut map = events::EventMap::new(self.age);
         // get all buildings' events
         for q in &self.qrtrs {
-            for b in &q.borrow().bldgs {
+            let q = q.borrow();
+            for b in &q.bldgs {
                 // get all EventChances
-                let eventcs = {
+                let (eventcs, origin) = {
                     let bldg = b.borrow();
-                    bldg.get_events()
+                    (bldg.get_events(),
+                     events::Origin { quarter: q.name.clone(), building: bldg.name.clone() })
                 };
-                map.add_chances(eventcs);
+                map.add_chances(eventcs, origin);
             }
         }
         map
@@ -99,11 +203,11 @@ impl Sett {
     /// times the growth bonus.
     pub fn add_quarter(&mut self,
                        n: String,
-                       qt: quarters::QType,
-                       r: people::Race,
+                       qt: Rc<quarters::QuarterType>,
+                       r: Rc<people::Race>,
     ) -> Result<(), quarters::BuildError>
     {
-        if qt == quarters::QType::Port && !self.coastal {
+        if qt.coastal && !self.coastal {
             return Err(quarters::BuildError::InlandPort);
         }
         let ref mut qrtrs = self.qrtrs;
@@ -111,12 +215,11 @@ impl Sett {
         if qrtrs.iter().any(|ref x| x.borrow().name == n) {
             return Err(quarters::BuildError::AlreadyExists);
         }
-        // TODO: add logic for when new quarter should be added
         let newpop = 50.0 * self.reg.growth;
         qrtrs.push(Rc::new(
                 RefCell::new(quarters::Quarter::new(&n, qt, newpop, r))));
         // receive gold bonus
-        self.gold += 100.0;
+        self.transact(ledger::Category::Founding, Some(n), 100.0);
         self.nextqrtr *= 2;
         self.pop += newpop;
         Ok(())
@@ -150,21 +253,146 @@ impl Sett {
             }
         }
 
-        self.gold -= plan.cost;
-        q.borrow_mut().add_building(plan)
+        let cost = plan.cost;
+        q.borrow_mut().add_building(plan)?;
+        let qname = q.borrow().name.clone();
+        self.transact(ledger::Category::Construction, Some(qname), -cost);
+        Ok(())
+    }
+
+    /// Add the given amount of gold (negative for spending) to the treasury,
+    /// recording it in the ledger under the given category and quarter.
+    pub fn transact(&mut self, category: ledger::Category, quarter: Option<String>,
+                    amount: f64)
+    {
+        self.gold += amount;
+        self.ledger.record(self.age, category, quarter, amount);
     }
 
     /// Increment the total amount of gold in the settlement based on the
     /// state of its quarters. For each member of the population, collect
-    /// 0.01 gold times the optional boost.
+    /// 0.01 gold times the sett's gold boost.
+    /// Then pay the upkeep of each building in turn, neglecting any that the
+    /// treasury cannot cover (see Building::neglect).
     pub fn collect_gold(&mut self) {
         //TODO: placeholder incrementer
-        let boost = self.boosts.gold.next().unwrap_or(1.0);
-        self.gold += 0.01f64 * boost * self.pop;
+        let boosts = self.boosts.resolve(&effects::Boosts::default());
+        let tax = boosts.gold.mul * 0.01f64 * self.pop;
+        self.transact(ledger::Category::Tax, None, tax);
+        self.transact(ledger::Category::Events, None, boosts.gold.add);
+        let income = self.qrtrs.iter().map(|q| {
+            let q = q.borrow();
+            (q.name.clone(), q.collect_gold(&boosts))
+        }).collect::<Vec<_>>();
+        for (qname, amounts) in income {
+            for (category, amount) in amounts {
+                self.transact(category, Some(qname.clone()), amount);
+            }
+        }
+        self.upkeep = 0.0;
+        for (qname, b) in self.get_buildings() {
+            let mut b = b.borrow_mut();
+            let upkeep = b.get_upkeep();
+            if upkeep <= 0.0 {
+                continue;
+            }
+            if self.gold >= upkeep {
+                self.transact(ledger::Category::Upkeep, Some(qname), -upkeep);
+                self.upkeep += upkeep;
+                b.pay_upkeep();
+            } else {
+                self.visitors.append(&mut b.neglect());
+            }
+        }
+    }
+
+    /// Count down a step on the effects of the sett and each of its
+    /// quarters and buildings.
+    fn tick_effects(&mut self) {
+        self.boosts.tick();
+        for q in &self.qrtrs {
+            q.borrow_mut().boosts.tick();
+        }
+        for (_, b) in self.get_buildings() {
+            b.borrow_mut().boosts.tick();
+        }
+    }
+
+    /// Return every active effect in the sett, with the name of the area it
+    /// was added to.
+    pub fn get_effects(&self) -> Vec<(String, effects::Modifier)> {
+        let mut v = self.boosts.mods.iter()
+            .map(|m| (self.name.clone(), m.clone())).collect::<Vec<_>>();
+        for q in &self.qrtrs {
+            let q = q.borrow();
+            v.extend(q.boosts.mods.iter()
+                     .map(|m| (format!("the {} Quarter", q.name), m.clone())));
+        }
+        for (q, b) in self.get_buildings() {
+            let b = b.borrow();
+            v.extend(b.boosts.mods.iter()
+                     .map(|m| (format!("the {} in the {} Quarter", b.name, q), m.clone())));
+        }
+        v
+    }
+
+    /// Remove every dying hero from their building (or from the visitors),
+    /// and add them to the graveyard. Any items they carried are kept in
+    /// their headquarters, or else in the first other building that can
+    /// hold them (see Building::can_hold).
+    /// Return the new graves, and each item which no building could hold
+    /// (and which was lost) with the name of the hero who carried it.
+    pub fn bury_dead(&mut self) -> (&[people::Grave], Vec<LostItem>) {
+        let first = self.graveyard.len();
+        // the items of each dead hero, with their headquarters (if any)
+        let mut carried = vec![];
         for q in &self.qrtrs {
-            self.gold += q.borrow_mut().collect_gold() * boost;
+            let q = q.borrow();
+            for b in &q.bldgs {
+                let (dead, alive) : (Vec<_>, Vec<_>) = b.borrow_mut().occupants.drain(..)
+                    .partition(|h| h.borrow().is_dying());
+                b.borrow_mut().occupants = alive;
+                for h in dead {
+                    let items = h.borrow_mut().items.drain(..).collect::<Vec<_>>();
+                    carried.push((h.borrow().name.clone(), Some(b.clone()), items));
+                    let place = format!("the {} in the {} Quarter", b.borrow().name, q.name);
+                    self.graveyard.push(people::Grave::new(h, self.age, &place));
+                }
+            }
+        }
+        let (dead, alive) : (Vec<_>, Vec<_>) = self.visitors.drain(..)
+            .partition(|h| h.borrow().is_dying());
+        self.visitors = alive;
+        for h in dead {
+            let items = h.borrow_mut().items.drain(..).collect::<Vec<_>>();
+            carried.push((h.borrow().name.clone(), None, items));
+            self.graveyard.push(people::Grave::new(h, self.age, &self.name));
         }
-        self.gold += self.boosts.gold_bonus.next().unwrap_or(0.0);
+        let mut lost = vec![];
+        for (hero, hq, items) in carried {
+            for item in items {
+                let kept = hq.iter().cloned()
+                    .chain(self.get_buildings().into_iter().map(|(_, b)| b))
+                    .any(|b| b.borrow_mut().add_item(item.clone()).is_ok());
+                if !kept {
+                    lost.push((hero.clone(), item));
+                }
+            }
+        }
+        (&self.graveyard[first..], lost)
+    }
+
+    /// Return a random building in use which heroes of the given class may
+    /// make their headquarters, if any exists.
+    pub fn rand_headquarters<R: Rng>(&self, class: &people::Class, rng: &mut R)
+        -> Option<Rc<RefCell<buildings::Building>>>
+    {
+        let valid = self.get_buildings().into_iter().map(|(_, b)| b)
+            .filter(|b| {
+                let b = b.borrow();
+                b.cond.is_in_use() && !b.is_abandoned() && b.accepts_class(class)
+            }).collect::<Vec<_>>();
+        rng.choose(&valid).cloned()
     }
 
     /// Return a wrapped Quarter if one by the given name can be found.
@@ -218,6 +446,8 @@ impl Sett {
             .and_then(|b| b.borrow().find_hero(hname))
     }
 
+    /// Return a Vec of (quarter name, building name, wrapped Hero) triples for
+    /// each hero in the settlement. Visitors are listed as being in town.
     pub fn get_heroes(&self) -> Vec<(String, String, Rc<RefCell<people::Hero>>)> {
         let bldgs = self.get_buildings();
         let qbhs = bldgs.iter().map(|&(ref q, ref b)| (q.clone(),
@@ -230,20 +460,24 @@ impl Sett {
                 v.push((q.clone(), b.clone(), h.clone()))
             }
         }
+        for h in self.visitors.iter() {
+            v.push((self.name.clone(), String::from("town"), h.clone()))
+        }
         v
     }
 
+    /// Return the heroes currently governing the settlement.
+    pub fn get_governors(&self) -> Vec<Rc<RefCell<people::Hero>>> {
+        self.get_heroes().into_iter().map(|(_, _, h)| h)
+            .filter(|h| h.borrow().is_governing()).collect()
+    }
+
     pub fn find_heroes(&self, hname: &str)
         -> Vec<(String, String, Rc<RefCell<people::Hero>>)>
     {
-        // get all buildings
-        let bldgs = self.get_buildings();
-        // map (qname, b) to (qname, bname, h)
-        bldgs.iter().map(|&(ref q, ref b)| (q.clone(), b.borrow().name(), b.borrow().find_hero(hname)))
-            .filter_map(|(q, b, oh)| match oh {
-                Some(h) => Some((q, b, h)),
-                None => None,
-            }).collect::<Vec<_>>()
+        self.get_heroes().into_iter()
+            .filter(|(_, _, h)| h.borrow().name == hname)
+            .collect::<Vec<_>>()
     }
 }
 
@@ -255,14 +489,234 @@ impl Described for Sett {
 
 impl fmt::Display for Sett {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unpaid = self.get_buildings().iter()
+            .filter(|&(_, b)| b.borrow().unpaid > 0).count();
         write!(f, "{}, located in {} {}.\n\
-               steps: {} | pop: {} | gold: {}\n\
+               steps: {} ({}) | pop: {} | gold: {}\n\
+               upkeep: {:.2} gold per step | unpaid: {} building(s)\n\
                Quarters:\n{}",
                self.name,
                if self.coastal { "coastal" } else { "inland" },
-               self.reg, self.age, self.pop as i64, self.gold as i64,
+               self.reg, self.age, self.season(), self.pop as i64, self.gold as i64,
+               self.upkeep, unpaid,
                self.qrtrs.iter().map(|q| {
                    format!("- {}\n", *(q.borrow()))
                }).collect::<String>())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regions::Region;
+    use rng;
+
+    fn qtype(name: &str, founding: f64) -> Rc<quarters::QuarterType> {
+        Rc::new(quarters::QuarterType {
+            name: name.to_string(),
+            desc: String::new(),
+            growth: 0.01,
+            coastal: false,
+            founding,
+            tags: vec![name.to_string()],
+        })
+    }
+
+    fn sett() -> Sett {
+        let reg = Rc::new(Region {
+            name: String::from("Plains"),
+            desc: String::new(),
+            growth: 1.0,
+            starting_gold: 500.0,
+        });
+        Sett::new(String::from("Testville"), reg, qtype("Residential", 0.001),
+                  Rc::new(people::Race::default()), false, None)
+    }
+
+    fn plan(name: &str, capacity: usize) -> Rc<buildings::BuildingPlan> {
+        Rc::new(buildings::BuildingPlan { capacity, ..buildings::BuildingPlan::test(name) })
+    }
+
+    /// Add a building in use to the sett's first quarter and return it.
+    fn build(s: &Sett, plan: Rc<buildings::BuildingPlan>) -> Rc<RefCell<buildings::Building>> {
+        let q = s.qrtrs[0].clone();
+        let name = plan.name.clone();
+        q.borrow_mut().add_building(plan).unwrap();
+        let b = q.borrow().find_building(&name).unwrap();
+        b.borrow_mut().cond = buildings::BldgCond::InUse(100.0);
+        b
+    }
+
+    fn hero(name: &str) -> Rc<RefCell<people::Hero>> {
+        people::Hero::test(name, people::Class::default())
+    }
+
+    fn item(name: &str) -> Rc<RefCell<items::Item>> {
+        let kind = Rc::new(items::ItemKind {
+            name: String::from("Book"),
+            desc: String::new(),
+            buildings: vec![String::from("ALL")],
+            magic: 1.0,
+            max_power: 0,
+            income: 0.0,
+        });
+        Rc::new(RefCell::new(items::Item::new(name, kind, 0, 10.0)))
+    }
+
+    /// Make the hero die carrying the given items.
+    fn die_with(h: &Rc<RefCell<people::Hero>>, items: Vec<Rc<RefCell<items::Item>>>) {
+        let mut h = h.borrow_mut();
+        h.items = items;
+        h.activity = people::Activity::Dying(String::from("of old age"));
+    }
+
+    #[test]
+    fn growth_boosts_apply_once_through_the_quarters() {
+        let mut s = sett();
+        let mut rng = rng::SimRng::new(1);
+        s.boosts.add(effects::Modifier::new("Growth", "the test", effects::Target::Grow,
+                                            effects::Combine::Mul, 1.5, 5));
+        for _ in 0..5 {
+            s.step(&[], &mut rng);
+            let sum = s.qrtrs.iter().map(|q| q.borrow().pop).sum::<f64>();
+            assert_eq!(s.pop, sum);
+        }
+    }
+
+    #[test]
+    fn dead_heroes_leave_items_in_their_headquarters() {
+        let mut s = sett();
+        let b = build(&s, plan("Library", 2));
+        let h = hero("Ann");
+        b.borrow_mut().add_occupant(h.clone()).unwrap();
+        die_with(&h, vec![item("Tome")]);
+        let (graves, lost) = s.bury_dead();
+        assert_eq!(graves.len(), 1);
+        assert!(lost.is_empty());
+        assert_eq!(b.borrow().items.len(), 1);
+        assert!(b.borrow().occupants.is_empty());
+    }
+
+    #[test]
+    fn items_overflow_into_other_buildings() {
+        let mut s = sett();
+        let hq = build(&s, plan("Shrine", 0));
+        let other = build(&s, plan("Library", 2));
+        let h = hero("Ann");
+        hq.borrow_mut().add_occupant(h.clone()).unwrap();
+        die_with(&h, vec![item("Tome"), item("Scroll")]);
+        let (_, lost) = s.bury_dead();
+        assert!(lost.is_empty());
+        assert_eq!(other.borrow().items.len(), 2);
+    }
+
+    #[test]
+    fn items_no_building_can_hold_are_lost() {
+        let mut s = sett();
+        let b = build(&s, plan("Library", 1));
+        let h = hero("Ann");
+        s.visitors.push(h.clone());
+        die_with(&h, vec![item("Tome"), item("Scroll")]);
+        let (graves, lost) = s.bury_dead();
+        assert_eq!(graves.len(), 1);
+        assert_eq!(b.borrow().items.len(), 1);
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].0, "Ann");
+        assert!(s.visitors.is_empty());
+    }
+
+    #[test]
+    fn headquarters_are_chosen_from_class_buildings() {
+        let s = sett();
+        let shrine = build(&s, plan("Shrine", 1));
+        build(&s, plan("Forge", 1));
+        let class = people::Class {
+            bldgs: vec![String::from("Shrine")],
+            ..people::Class::default()
+        };
+        let mut rng = rng::SimRng::new(1);
+        for _ in 0..10 {
+            let hq = s.rand_headquarters(&class, &mut rng).unwrap();
+            assert!(Rc::ptr_eq(&hq, &shrine));
+        }
+        shrine.borrow_mut().cond = buildings::BldgCond::Ruined;
+        assert!(s.rand_headquarters(&class, &mut rng).is_none());
+    }
+
+    #[test]
+    fn founding_chance_grows_with_population() {
+        let mut s = sett();
+        let before = s.founding_chance(&[]);
+        s.pop *= 10.0;
+        assert!(s.founding_chance(&[]) > before);
+    }
+
+    #[test]
+    fn founding_chance_grows_with_age() {
+        let mut s = sett();
+        let before = s.founding_chance(&[]);
+        s.age += 100;
+        assert!(s.founding_chance(&[]) > before);
+    }
+
+    #[test]
+    fn new_quarter_raises_population_needed() {
+        let mut s = sett();
+        s.add_quarter(String::from("Second"), qtype("Residential", 0.001),
+                      Rc::new(people::Race::default())).unwrap();
+        assert_eq!(s.nextqrtr, 100);
+        // the same population now counts for half as much (with no
+        // buildings and no age to add)
+        let x = FOUND_BASE + FOUND_POP * s.pop / 100.0;
+        assert!((s.founding_chance(&[]) - 1.0 / (1.0 + (-x).exp())).abs() < 1e-12);
+    }
+
+    #[test]
+    fn upkeep_is_paid_until_the_treasury_runs_dry() {
+        let mut s = sett();
+        let b = build(&s, Rc::new(buildings::BuildingPlan {
+            upkeep: 5.0,
+            ..buildings::BuildingPlan::test("Mill")
+        }));
+        let h = hero("Ann");
+        b.borrow_mut().add_occupant(h.clone()).unwrap();
+        let upkeep_paid = |s: &Sett| s.ledger.entries.iter()
+            .filter(|e| e.category == ledger::Category::Upkeep).count();
+        s.gold = 10.0;
+        s.collect_gold();
+        assert_eq!(upkeep_paid(&s), 1);
+        assert_eq!(b.borrow().unpaid, 0);
+        // the sett's taxes alone cannot cover the upkeep
+        s.gold = 0.0;
+        s.collect_gold();
+        assert_eq!(b.borrow().unpaid, 1);
+        while !b.borrow().is_abandoned() {
+            s.gold = 0.0;
+            s.collect_gold();
+        }
+        assert!(s.visitors.iter().any(|v| Rc::ptr_eq(v, &h)));
+        // abandoned buildings are no longer charged
+        s.gold = 100.0;
+        s.collect_gold();
+        assert_eq!(upkeep_paid(&s), 1);
+        assert!(b.borrow().is_abandoned());
+    }
+
+    #[test]
+    fn buildings_weigh_by_their_own_type() {
+        let s = sett();
+        let qtypes = vec![qtype("Residential", 0.001), qtype("Academic", 0.5)];
+        let before = s.founding_chance(&qtypes);
+        // an academic building in a residential quarter counts as academic
+        let plan = Rc::new(buildings::BuildingPlan {
+            btype: String::from("Academic"),
+            ..buildings::BuildingPlan::test("Library")
+        });
+        s.qrtrs[0].borrow_mut().add_building(plan).unwrap();
+        let x = FOUND_BASE + FOUND_POP * s.pop / s.nextqrtr as f64 + 0.5;
+        assert!((s.founding_chance(&qtypes) - 1.0 / (1.0 + (-x).exp())).abs() < 1e-12);
+        assert!(s.founding_chance(&qtypes) > before);
+        // buildings of undeclared types add nothing
+        assert_eq!(s.founding_chance(&qtypes[..1]), before);
+    }
+}
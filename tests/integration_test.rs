@@ -1,6 +1,37 @@
 extern crate podesta;
 
+use podesta::manager::Manager;
+use podesta::libdata::PathList;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
 #[test]
 fn it_works() {
 
 }
+
+/// Build a settlement automatically from the given seed, step it and save it,
+/// returning the bytes of the save file.
+fn seeded_run(seed: u64, file: &str) -> Vec<u8> {
+    let pl = PathList::from_dirs("lib/data/", "lib/names/").unwrap();
+    let mut man = Manager::new(&pl, false, Some(seed)).unwrap();
+    man.toggle_auto();
+    man.build_sett(Some("Testville".to_string()), true);
+    man.build_building(Some("Shrine".to_string()), Some("Main".to_string()));
+    man.step(30);
+    let path = env::temp_dir().join(file);
+    let path = path.to_str().unwrap().to_string();
+    man.save(Some(path.clone())).unwrap();
+    let mut bytes = vec![];
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn same_seed_same_sett() {
+    let a = seeded_run(1234, "podesta-seed-a.rbs");
+    let b = seeded_run(1234, "podesta-seed-b.rbs");
+    assert_eq!(a, b);
+}